@@ -0,0 +1,142 @@
+use crate::chunks::*;
+use crate::util::*;
+
+// Slots never move once they're allocated; freed slots get threaded onto a free
+// list and reused by later allocations. This is what lets us detach and move
+// whole subtrees around without fixing up every index that happened to get
+// swapped into a hole.
+pub(crate) struct Arena<T> {
+    slots: ChunkVec<Slot<T>>,
+    next_free: Option<Idx>,
+}
+
+#[derive(Clone)]
+pub(crate) enum Slot<T> {
+    Full(T),
+    Free(Option<Idx>),
+}
+
+impl<T> Arena<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Self {
+        return Self {
+            slots: ChunkVec::new(),
+            next_free: None,
+        };
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        return Self {
+            slots: ChunkVec::with_capacity(capacity),
+            next_free: None,
+        };
+    }
+
+    pub fn alloc(&mut self, value: T) -> Idx {
+        let idx = match self.next_free {
+            Some(idx) => idx,
+            None => {
+                self.slots.push(Slot::Full(value));
+                return Idx::new(self.slots.len() - 1);
+            }
+        };
+
+        let slot = core::mem::replace(&mut self.slots[idx.get()], Slot::Full(value));
+        self.next_free = match slot {
+            Slot::Free(next) => next,
+            Slot::Full(_) => core::unreachable!("free list pointed at a full slot"),
+        };
+
+        return idx;
+    }
+
+    pub fn free(&mut self, idx: Idx) -> T {
+        let slot = Slot::Free(self.next_free);
+        let value = match core::mem::replace(&mut self.slots[idx.get()], slot) {
+            Slot::Full(value) => value,
+            Slot::Free(_) => core::panic!("freed an arena slot twice"),
+        };
+
+        self.next_free = Some(idx);
+
+        return value;
+    }
+
+    // Unlike indexing, this doesn't panic on a freed slot
+    pub fn get(&self, index: usize) -> Option<&T> {
+        return match self.slots.get(index)? {
+            Slot::Full(value) => Some(value),
+            Slot::Free(_) => None,
+        };
+    }
+
+    // Freed slots count as allocated
+    pub fn allocated_bytes(&self) -> usize {
+        return self.slots.allocated_bytes();
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.slots.shrink_to_fit();
+    }
+
+    #[cfg(test)]
+    pub fn shared_chunks(&self) -> usize {
+        return self.slots.shared_chunks();
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.next_free = None;
+    }
+
+    pub fn share(&self) -> Self {
+        return Self {
+            slots: self.slots.share(),
+            next_free: self.next_free,
+        };
+    }
+}
+
+// Slot `i` gets the `i`th value
+impl<T> FromIterator<T> for Arena<T>
+where
+    T: Clone,
+{
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+    {
+        return Self {
+            slots: iter.into_iter().map(Slot::Full).collect(),
+            next_free: None,
+        };
+    }
+}
+
+impl<T> core::ops::Index<usize> for Arena<T>
+where
+    T: Clone,
+{
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        return match &self.slots[index] {
+            Slot::Full(value) => value,
+            Slot::Free(_) => core::panic!("accessed a freed arena slot"),
+        };
+    }
+}
+
+impl<T> core::ops::IndexMut<usize> for Arena<T>
+where
+    T: Clone,
+{
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        return match &mut self.slots[index] {
+            Slot::Full(value) => value,
+            Slot::Free(_) => core::panic!("accessed a freed arena slot"),
+        };
+    }
+}
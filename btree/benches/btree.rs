@@ -0,0 +1,155 @@
+// Plain timing loops, so that benchmarking doesn't need any dependencies.
+// Run with `cargo bench --manifest-path ./btree/Cargo.toml`.
+//
+// Baseline: the array-of-structs layout from before `B` was a parameter
+// (B = 6, with kids stored as `[Option<Idx>; 6]`), run through these same
+// loops, against the default B = 32 with the current layout. Both ran three
+// times, taking turns on the same machine, and this is the best run of each,
+// in ns/op:
+//
+//                   old layout   B = 32
+//   build                83        38
+//   get by index        570       471
+//   key lookup          617       327
+//   count_until         960       739
+//   iterate              15        10
+//   cursor seek        1124       472
+//   insert             1120       853
+//   remove             2378      1611
+//
+// B = 16 was close on lookups but slower at inserting (1152) and removing
+// (1979). B = 64 inserts faster still, but every kind of lookup got slower.
+
+use btree::*;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const SIZE: usize = 1_000_000;
+const OPS: usize = 1_000_000;
+
+#[derive(Clone, Copy, Default)]
+struct Data(usize);
+
+impl BTreeInfo for Data {
+    fn add(self, other: Self) -> Self {
+        return Self(self.0 + other.0);
+    }
+}
+
+impl BTreeItem for Data {
+    type Info = Self;
+
+    fn get_info(&self) -> Self::Info {
+        return *self;
+    }
+}
+
+struct Value;
+
+impl Metric<Data> for Value {
+    fn measure(_count: usize, info: Data) -> usize {
+        return info.0;
+    }
+}
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self, max: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        return (self.0 >> 33) as usize % max;
+    }
+}
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    let start = Instant::now();
+    f();
+    return start.elapsed();
+}
+
+fn report(name: &str, elapsed: Duration, ops: usize) {
+    let per_op = elapsed.as_nanos() as f64 / ops as f64;
+    println!("{:<28} {:>10.1?} {:>8.1} ns/op", name, elapsed, per_op);
+}
+
+fn main() {
+    let mut rng = Rng(12345);
+    let indices: Vec<usize> = (0..OPS).map(|_| rng.next(SIZE)).collect();
+
+    bench::<6>(&indices);
+    bench::<8>(&indices);
+    bench::<16>(&indices);
+    bench::<32>(&indices);
+    bench::<64>(&indices);
+}
+
+fn bench<const B: usize>(indices: &[usize]) {
+    println!(
+        "B = {}{}",
+        B,
+        if B == DEFAULT_B { " (default)" } else { "" }
+    );
+
+    let mut tree = None;
+    let elapsed = time(|| tree = Some((0..SIZE).map(|_| Data(3)).collect::<BTree<Data, B>>()));
+    report("build", elapsed, SIZE);
+    let tree = tree.unwrap();
+
+    let elapsed = time(|| {
+        for &i in indices {
+            black_box(tree.get(i));
+        }
+    });
+    report("get by index", elapsed, OPS);
+
+    let elapsed = time(|| {
+        for &i in indices {
+            black_box(tree.key::<Value>(i * 3 + 1));
+        }
+    });
+    report("key lookup", elapsed, OPS);
+
+    let handles: Vec<_> = indices.iter().map(|&i| tree.get_idx(i).unwrap()).collect();
+    let elapsed = time(|| {
+        for &handle in &handles {
+            black_box(tree.count_until(handle));
+        }
+    });
+    report("count_until", elapsed, OPS);
+
+    let elapsed = time(|| {
+        let mut sum = 0;
+        for data in tree.iter() {
+            sum += data.0;
+        }
+        black_box(sum);
+    });
+    report("iterate", elapsed, SIZE);
+
+    let elapsed = time(|| {
+        let mut cursor = tree.cursor();
+        for &i in indices {
+            black_box(cursor.seek::<Value>(i * 3 + 1));
+        }
+    });
+    report("cursor seek", elapsed, OPS);
+
+    let mut tree = tree;
+    let elapsed = time(|| {
+        for (n, &i) in indices.iter().enumerate() {
+            tree.insert(i % (SIZE + n), Data(1));
+        }
+    });
+    report("insert", elapsed, OPS);
+
+    let elapsed = time(|| {
+        for &i in indices {
+            black_box(tree.remove(i));
+        }
+    });
+    report("remove", elapsed, OPS);
+    println!();
+}
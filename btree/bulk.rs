@@ -0,0 +1,205 @@
+use crate::handles::*;
+use crate::nodes::*;
+use crate::traits::*;
+use crate::tree::*;
+use crate::util::*;
+use alloc::vec::Vec;
+
+impl<T, const B: usize> BTree<T, B>
+where
+    T: BTreeItem,
+{
+    // Builds the tree bottom-up out of packed nodes, so the whole thing is O(n)
+    // instead of n inserts that each might split their way up to the root.
+    // Nodes on a level are filled as evenly as possible, which keeps every
+    // non-root node at least half full.
+    pub(crate) fn build(elements: Vec<T>) -> Self {
+        let mut tree = Self::default();
+        if elements.is_empty() {
+            return tree;
+        }
+
+        // Every column gets filled in one go, with element `i` in slot `i`
+        // and given handle `i`. Parents get filled in once the leaves exist.
+        let len = elements.len();
+        tree.nodes.clear();
+        tree.elements = elements.into_iter().collect();
+        tree.handles = Handles::sequential(len);
+        tree.element_parents = core::iter::repeat_n(Idx::new(0), len).collect();
+        tree.element_handles = (0..len).map(Idx::new).collect();
+
+        tree.build_nodes((0..len).map(Idx::new).collect());
+        return tree;
+    }
+
+    /// Removes every element that `f` returns false for, visiting elements in
+    /// order. Nodes whose elements all get kept aren't touched, and nodes
+    /// whose elements all get removed are freed whole; only the nodes that
+    /// are partly kept get rebuilt and rebalanced. Elements that are kept keep
+    /// their `ElemIdx`.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let root = self.root;
+        if !self.retain_in(root, &mut f) {
+            return;
+        }
+
+        if self.nodes.kids(root).len() == 0 {
+            self.nodes.free(root);
+            self.root = self.nodes.alloc(Node::empty(true));
+            self.levels = 0;
+            return;
+        }
+
+        self.collapse_root();
+    }
+
+    // Runs `retain` on the subtree under `node`, and returns whether anything
+    // in it got removed. Kids that end up empty get freed, and the rest get
+    // rebalanced, so afterwards every node under `node` is at least half full;
+    // `node` itself can end up with any number of kids, including zero.
+    fn retain_in<F>(&mut self, node: Idx, f: &mut F) -> bool
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let is_leaf = self.nodes.is_leaf(node);
+        let kids = *self.nodes.kids(node);
+        let mut kept = Kids::new();
+        let mut changed = false;
+        for (kid, summary) in kids.iter().zip(kids.summaries()) {
+            if is_leaf {
+                match f(&self.elements[kid.get()]) {
+                    true => kept.push(kid, summary),
+                    false => {
+                        self.free_elem(kid);
+                        changed = true;
+                    }
+                }
+
+                continue;
+            }
+
+            if !self.retain_in(kid, f) {
+                kept.push(kid, summary);
+                continue;
+            }
+
+            changed = true;
+            match self.nodes.kids(kid).len() {
+                0 => {
+                    self.nodes.free(kid);
+                }
+                _ => kept.push(kid, self.nodes.summary(kid)),
+            }
+        }
+
+        if !changed {
+            return false;
+        }
+
+        *self.nodes.kids_mut(node) = kept;
+        if !is_leaf {
+            self.fill_kids(node);
+        }
+
+        self.update_node(node);
+        return true;
+    }
+
+    // Merges or balances any kid of `node` that's underfull with one of its
+    // neighbors, and then does the same inside of the kids that changed, since
+    // a kid that was left with just one kid of its own can have an underfull
+    // kid that it couldn't fix by itself.
+    fn fill_kids(&mut self, node: Idx) {
+        let mut index = 0;
+        while index < self.nodes.kids(node).len() && self.nodes.kids(node).len() > 1 {
+            let kids = self.nodes.kids(node);
+            if self.nodes.kids(kids[index]).len() >= Nodes::<T::Info, B>::MIN_KIDS {
+                index += 1;
+                continue;
+            }
+
+            if index + 1 == kids.len() {
+                index -= 1;
+            }
+
+            let (left, right) = (kids[index], kids[index + 1]);
+            let merged = self.merge_or_balance(left, right);
+            if merged {
+                self.remove_node(right);
+            }
+
+            if !self.nodes.is_leaf(left) {
+                self.fill_kids(left);
+                if !merged {
+                    self.fill_kids(right);
+                }
+            }
+        }
+    }
+
+    // Builds nodes bottom-up on top of `level`, which has to be non-empty and
+    // have the elements in order. The tree can't have any nodes yet.
+    fn build_nodes(&mut self, mut level: Vec<Idx>) {
+        let mut is_leaf = true;
+        loop {
+            let mut next_level = Vec::with_capacity(level.len() / B + 1);
+            for group in packed_groups(level.len(), B) {
+                let kids = Kids::from(&level[group]);
+                next_level.push(self.new_node(is_leaf, kids));
+            }
+
+            level = next_level;
+            if level.len() == 1 {
+                break;
+            }
+
+            is_leaf = false;
+            self.levels += 1;
+        }
+
+        self.root = level[0];
+    }
+}
+
+// Splits `len` kids into the fewest possible groups of at most `max`, with
+// sizes differing by at most one.
+fn packed_groups(len: usize, max: usize) -> impl Iterator<Item = core::ops::Range<usize>> {
+    let group_count = len.div_ceil(max);
+    let (size, extra) = (len / group_count, len % group_count);
+
+    let mut begin = 0;
+    return (0..group_count).map(move |i| {
+        let end = begin + size + (i < extra) as usize;
+        let range = begin..end;
+        begin = end;
+
+        return range;
+    });
+}
+
+impl<T, const B: usize> FromIterator<T> for BTree<T, B>
+where
+    T: BTreeItem,
+{
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+    {
+        return Self::build(iter.into_iter().collect());
+    }
+}
+
+impl<T, const B: usize> Extend<T> for BTree<T, B>
+where
+    T: BTreeItem,
+{
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = T>,
+    {
+        self.append_moving(Self::build(iter.into_iter().collect()));
+    }
+}
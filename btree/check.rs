@@ -0,0 +1,145 @@
+use crate::nodes::*;
+use crate::traits::*;
+use crate::tree::*;
+use crate::util::*;
+use alloc::vec::Vec;
+
+// Which node broke which rule. Node indices are the same ones that show up in
+// the `Debug` output for nodes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InvariantError {
+    pub node: usize,
+    pub reason: &'static str,
+}
+
+impl core::fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        return write!(f, "node {}: {}", self.node, self.reason);
+    }
+}
+
+impl<T, const B: usize> BTree<T, B>
+where
+    T: BTreeItem,
+    T::Info: PartialEq,
+{
+    // Walks the whole tree and checks all of the bookkeeping. This is O(n), so
+    // it's meant for tests and for debugging.
+    pub fn check_invariants(&self) -> Result<(), InvariantError> {
+        let mut order = Vec::new();
+        let mut stack = Vec::with_capacity(self.levels * B + 1);
+        stack.push((self.root, 0));
+
+        while let Some((node, depth)) = stack.pop() {
+            let node_ref = match self.nodes.row(node) {
+                Some(node_ref) => node_ref,
+                None => {
+                    let reason = "node was freed";
+                    return Err(InvariantError {
+                        node: node.get(),
+                        reason,
+                    });
+                }
+            };
+
+            order.push((node, depth));
+            if !node_ref.is_leaf && depth < self.levels {
+                stack.extend(node_ref.kids.iter().map(|kid| (kid, depth + 1)));
+            }
+        }
+
+        // Kids before parents, so that a bad cache gets blamed on the node that
+        // actually has it, and not on every node above that one
+        for &(node, depth) in order.iter().rev() {
+            if let Err(reason) = self.check_node(node, depth) {
+                return Err(InvariantError {
+                    node: node.get(),
+                    reason,
+                });
+            }
+        }
+
+        return Ok(());
+    }
+
+    // Checks the things that `node` is responsible for: its own position in the
+    // tree, its kids pointing back at it, and its cached count and info. Kids
+    // are trusted to have correct caches, since they get checked on their own.
+    pub(crate) fn check_node(&self, node: Idx, depth: usize) -> Result<(), &'static str> {
+        let node_ref = match self.nodes.row(node) {
+            Some(node_ref) => node_ref,
+            None => return Err("node was freed"),
+        };
+
+        if depth == 0 && node_ref.parent.is_some() {
+            return Err("root has a parent");
+        }
+
+        if node_ref.is_leaf != (depth == self.levels) {
+            return Err("leaves aren't all at the same depth");
+        }
+
+        let kid_count = node_ref.kids.len();
+        if depth > 0 && kid_count < Nodes::<T::Info, B>::MIN_KIDS {
+            return Err("node has too few kids");
+        }
+
+        if depth == 0 && self.levels > 0 && kid_count < 2 {
+            return Err("root has only one kid");
+        }
+
+        let (mut count, mut info) = (0, T::Info::default());
+        let slots = node_ref.kids.iter().zip(node_ref.kids.summaries());
+        for (kid, (slot_count, slot_info)) in slots {
+            if node_ref.is_leaf {
+                let elem = match self.elements.get(kid.get()) {
+                    Some(elem) => elem,
+                    None => return Err("element was freed"),
+                };
+
+                if self.element_parents[kid.get()] != node {
+                    return Err("element parent doesn't match");
+                }
+
+                let handle = self.element_handles[kid.get()];
+                if self.handles.resolve(self.handles.elem_idx(handle)) != Some(kid) {
+                    return Err("element handle doesn't resolve to the element");
+                }
+
+                if slot_count != 1 || slot_info != elem.get_info() {
+                    return Err("cached element summary is wrong");
+                }
+
+                count += 1;
+                info = info.add(elem.get_info());
+                continue;
+            }
+
+            let kid_ref = match self.nodes.row(kid) {
+                Some(kid_ref) => kid_ref,
+                None => return Err("kid node was freed"),
+            };
+
+            if kid_ref.parent != Some(node) {
+                return Err("kid's parent doesn't match");
+            }
+
+            if slot_count != kid_ref.count || slot_info != kid_ref.info {
+                return Err("cached kid summary is wrong");
+            }
+
+            count += kid_ref.count;
+            info = info.add(kid_ref.info);
+        }
+
+        if count != node_ref.count {
+            return Err("cached count is wrong");
+        }
+
+        if info != node_ref.info {
+            return Err("cached info is wrong");
+        }
+
+        return Ok(());
+    }
+}
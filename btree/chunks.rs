@@ -0,0 +1,161 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const CHUNK_SHIFT: usize = 6;
+const CHUNK_SIZE: usize = 1 << CHUNK_SHIFT;
+const CHUNK_MASK: usize = CHUNK_SIZE - 1;
+
+// A Vec that's split into reference counted chunks, so that copies of it can
+// share whatever chunks neither of them has written to. Writing to a shared
+// chunk copies just that chunk first. The counts are atomic so that a tree and
+// its snapshots can live on different threads.
+pub(crate) struct ChunkVec<T> {
+    chunks: Vec<Arc<Vec<T>>>,
+    len: usize,
+}
+
+impl<T> ChunkVec<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Self {
+        return Self {
+            chunks: Vec::new(),
+            len: 0,
+        };
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut sel = Self::new();
+        sel.reserve(capacity);
+        return sel;
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        let chunks = (self.len + additional + CHUNK_MASK) >> CHUNK_SHIFT;
+        self.chunks
+            .reserve(chunks.saturating_sub(self.chunks.len()));
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn push(&mut self, value: T) {
+        if self.len & CHUNK_MASK == 0 {
+            self.chunks.push(Arc::new(Vec::with_capacity(CHUNK_SIZE)));
+        }
+
+        let last = self.chunks.len() - 1;
+        self.chunk_mut(last).push(value);
+        self.len += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.len = 0;
+    }
+
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            return None;
+        }
+
+        return Some(&self.chunks[index >> CHUNK_SHIFT][index & CHUNK_MASK]);
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index >= self.len {
+            return None;
+        }
+
+        return Some(&mut self.chunk_mut(index >> CHUNK_SHIFT)[index & CHUNK_MASK]);
+    }
+
+    fn chunk_mut(&mut self, chunk: usize) -> &mut Vec<T> {
+        return Arc::make_mut(&mut self.chunks[chunk]);
+    }
+
+    // Bytes set aside for values, whether they're in use or not
+    pub fn allocated_bytes(&self) -> usize {
+        let capacity: usize = self.chunks.iter().map(|c| c.capacity()).sum();
+        return capacity * core::mem::size_of::<T>();
+    }
+
+    // Only the last chunk can have room left over, and it's left alone if
+    // it's shared, since shrinking it would mean copying it.
+    pub fn shrink_to_fit(&mut self) {
+        self.chunks.shrink_to_fit();
+        if let Some(last) = self.chunks.last_mut().and_then(Arc::get_mut) {
+            last.shrink_to_fit();
+        }
+    }
+
+    // Number of chunks that this shares with some other ChunkVec
+    #[cfg(test)]
+    pub fn shared_chunks(&self) -> usize {
+        return self
+            .chunks
+            .iter()
+            .filter(|c| Arc::strong_count(c) > 1)
+            .count();
+    }
+
+    // Makes a copy that shares all of its chunks with this one
+    pub fn share(&self) -> Self {
+        return Self {
+            chunks: self.chunks.clone(),
+            len: self.len,
+        };
+    }
+}
+
+// Fills each chunk before it gets shared with anything, so unlike pushing,
+// nothing has to check whether a chunk needs to be copied first
+impl<T> FromIterator<T> for ChunkVec<T>
+where
+    T: Clone,
+{
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut iter = iter.into_iter();
+        let mut sel = Self::with_capacity(iter.size_hint().0);
+        loop {
+            let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+            chunk.extend(iter.by_ref().take(CHUNK_SIZE));
+            if chunk.is_empty() {
+                return sel;
+            }
+
+            sel.len += chunk.len();
+            sel.chunks.push(Arc::new(chunk));
+        }
+    }
+}
+
+impl<T> core::ops::Index<usize> for ChunkVec<T>
+where
+    T: Clone,
+{
+    type Output = T;
+
+    #[inline]
+    fn index(&self, index: usize) -> &T {
+        return self.get(index).expect("index out of bounds");
+    }
+}
+
+impl<T> core::ops::IndexMut<usize> for ChunkVec<T>
+where
+    T: Clone,
+{
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        return self.get_mut(index).expect("index out of bounds");
+    }
+}
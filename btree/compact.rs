@@ -0,0 +1,122 @@
+use crate::arena::*;
+use crate::chunks::*;
+use crate::handles::*;
+use crate::nodes::*;
+use crate::traits::*;
+use crate::tree::*;
+use crate::util::*;
+use alloc::vec::Vec;
+
+// Only counts the tree's own storage; anything that the elements themselves
+// own isn't included.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MemoryStats {
+    pub elements: usize,
+    pub nodes: usize,
+    // Average number of kids per node, as a fraction of `B`
+    pub average_fill: f64,
+    pub allocated_bytes: usize,
+    // The part of `allocated_bytes` that isn't holding anything, i.e. freed
+    // slots and capacity that hasn't been used yet
+    pub wasted_bytes: usize,
+}
+
+impl<T, const B: usize> BTree<T, B>
+where
+    T: BTreeItem,
+{
+    pub fn memory_stats(&self) -> MemoryStats {
+        let (mut nodes, mut kids) = (0, 0);
+        let mut stack = Vec::with_capacity(self.levels * B + 1);
+        stack.push(self.root);
+        while let Some(node) = stack.pop() {
+            let node_kids = self.nodes.kids(node);
+            nodes += 1;
+            kids += node_kids.len();
+
+            if !self.nodes.is_leaf(node) {
+                stack.extend(node_kids.iter());
+            }
+        }
+
+        let elements = self.len();
+        let element_bytes = core::mem::size_of::<Slot<T>>() + 2 * core::mem::size_of::<Idx>();
+        let used_bytes = elements * (element_bytes + Handles::ROW_BYTES)
+            + nodes * Nodes::<T::Info, B>::ROW_BYTES;
+
+        let allocated_bytes = self.elements.allocated_bytes()
+            + self.element_parents.allocated_bytes()
+            + self.element_handles.allocated_bytes()
+            + self.handles.allocated_bytes()
+            + self.nodes.allocated_bytes();
+
+        return MemoryStats {
+            elements,
+            nodes,
+            average_fill: kids as f64 / (nodes * B) as f64,
+            allocated_bytes,
+            wasted_bytes: allocated_bytes.saturating_sub(used_bytes),
+        };
+    }
+
+    /// Moves every element and node into fresh storage with no holes in it.
+    /// Nodes end up in breadth-first order and elements in tree order, so
+    /// siblings are next to each other. ElemIdx's stay valid, since handles
+    /// just get pointed at the new slots. This is O(n).
+    pub fn compact(&mut self) {
+        let len = self.len();
+        let mut old_elements = core::mem::replace(&mut self.elements, Arena::with_capacity(len));
+        let old_handles = core::mem::replace(&mut self.element_handles, ChunkVec::new());
+        let old_nodes = core::mem::replace(&mut self.nodes, Nodes::new());
+        self.element_parents = ChunkVec::with_capacity(len);
+        self.element_handles.reserve(len);
+
+        // Kids get numbered as they're found, so every node's new index is
+        // its position in `order`
+        let mut order = Vec::new();
+        order.push((self.root, None));
+
+        let mut next = 0;
+        while next < order.len() {
+            let (old, parent) = order[next];
+            let idx = Idx::new(next);
+
+            let mut node = old_nodes.row(old).unwrap();
+            let is_leaf = node.is_leaf;
+            node.parent = parent;
+            for kid in &mut node.kids {
+                *kid = match is_leaf {
+                    true => {
+                        let handle = old_handles[kid.get()];
+                        let elem = self.elements.alloc(old_elements.free(*kid));
+                        self.element_parents.push(idx);
+                        self.element_handles.push(handle);
+                        self.handles.set(handle, elem);
+                        elem
+                    }
+                    false => {
+                        order.push((*kid, Some(idx)));
+                        Idx::new(order.len() - 1)
+                    }
+                };
+            }
+
+            let new_idx = self.nodes.alloc(node);
+            debug_assert!(new_idx == idx);
+            next += 1;
+        }
+
+        self.root = Idx::new(0);
+    }
+
+    /// Gives back capacity that isn't being used. Slots that were freed in
+    /// the middle of the tree's storage stay around; `compact` gets rid of
+    /// those.
+    pub fn shrink_to_fit(&mut self) {
+        self.elements.shrink_to_fit();
+        self.element_parents.shrink_to_fit();
+        self.element_handles.shrink_to_fit();
+        self.handles.shrink_to_fit();
+        self.nodes.shrink_to_fit();
+    }
+}
@@ -1,8 +1,9 @@
+use crate::metric::*;
 use crate::traits::*;
 use crate::tree::*;
 use crate::util::*;
 
-impl<T> BTree<T>
+impl<T, const B: usize> BTree<T, B>
 where
     T: BTreeItem,
 {
@@ -11,41 +12,65 @@ where
         return Some(&self.elements[idx.get()]);
     }
 
-    pub fn key<F>(&self, index: usize, get: F) -> Option<(&T, usize)>
+    pub fn key<M>(&self, key: usize) -> Option<(&T, usize)>
     where
-        F: Fn(T::Info) -> usize,
+        M: Metric<T>,
     {
-        let (idx, remainder) = self.find(false, index, move |_, info| get(info))?;
+        let (idx, remainder) = self.find::<M>(false, key)?;
         return Some((&self.elements[idx.get()], remainder));
     }
 
-    pub fn key_leq<F>(&self, index: usize, get: F) -> Option<(&T, usize)>
+    pub fn key_leq<M>(&self, key: usize) -> Option<(&T, usize)>
     where
-        F: Fn(T::Info) -> usize,
+        M: Metric<T>,
     {
-        let (idx, remainder) = self.find(true, index, move |_, info| get(info))?;
+        let (idx, remainder) = self.find::<M>(true, key)?;
         return Some((&self.elements[idx.get()], remainder));
     }
 
+    // Like `info_range`, but `range` is in terms of `M` instead of element
+    // indices. Elements can't be split, so this combines every element that
+    // overlaps the range; it also returns where the range begins inside of the
+    // first element and where it ends inside of the last one, so that callers
+    // can account for the parts of those elements that are outside the range.
+    pub fn info_key_range<M>(
+        &self,
+        range: core::ops::Range<usize>,
+    ) -> Option<(T::Info, usize, usize)>
+    where
+        M: Metric<T>,
+    {
+        if range.start >= range.end {
+            return Some((Default::default(), 0, 0));
+        }
+
+        let (first, begin) = self.find::<M>(false, range.start)?;
+        let (last, end) = self.find::<M>(false, range.end - 1)?;
+        let first = self.count_until(first)?;
+        let last = self.count_until(last)?;
+
+        return Some((self.info_range(first..=last), begin, end + 1));
+    }
+
     pub fn get_idx(&self, index: usize) -> Option<ElemIdx> {
-        let (idx, _) = self.find(false, index, |count, _| count)?;
-        return Some(ElemIdx(idx));
+        let (idx, _) = self.find::<Count>(false, index)?;
+        return Some(self.elem_idx(idx));
     }
 
-    pub fn key_idx<F>(&self, index: usize, get: F) -> Option<(ElemIdx, usize)>
+    pub fn key_idx<M>(&self, key: usize) -> Option<(ElemIdx, usize)>
     where
-        F: Fn(T::Info) -> usize,
+        M: Metric<T>,
     {
-        let (idx, remainder) = self.find(false, index, move |_, info| get(info))?;
-        return Some((ElemIdx(idx), remainder));
+        let (idx, remainder) = self.find::<M>(false, key)?;
+        return Some((self.elem_idx(idx), remainder));
     }
 
-    pub fn key_leq_idx<F>(&self, index: usize, get: F) -> Option<(ElemIdx, usize)>
+    pub fn key_leq_idx<M>(&self, key: usize) -> Option<(ElemIdx, usize)>
     where
-        F: Fn(T::Info) -> usize,
+        M: Metric<T>,
     {
-        let (idx, remainder) = self.find(true, index, move |_, info| get(info))?;
-        return Some((ElemIdx(idx), remainder));
+        let (idx, remainder) = self.find::<M>(true, key)?;
+        return Some((self.elem_idx(idx), remainder));
     }
 
     // We can't return a mutable reference here because we need to update the
@@ -62,10 +87,10 @@ where
         let elem = &mut self.elements[idx];
         let result = f(elem);
 
-        let mut node = self.element_parents[idx];
+        let mut node = self.refresh_elem(Idx::new(idx));
         self.update_node(node);
         for _ in 0..self.levels {
-            node = self.nodes[node.get()].parent.unwrap();
+            node = self.nodes.parent(node).unwrap();
             self.update_node(node);
         }
 
@@ -82,13 +107,13 @@ where
 
         let elem = &mut self.elements[idx];
         if f(elem) {
-            return self.remove(ElemIdx(Idx::new(idx)));
+            return self.remove(Idx::new(idx));
         }
 
-        let mut node = self.element_parents[idx];
+        let mut node = self.refresh_elem(Idx::new(idx));
         self.update_node(node);
         for _ in 0..self.levels {
-            node = self.nodes[node.get()].parent.unwrap();
+            node = self.nodes.parent(node).unwrap();
             self.update_node(node);
         }
 
@@ -98,17 +123,17 @@ where
     }
 
     pub fn last_idx(&self) -> Option<ElemIdx> {
-        let (idx, _) = self.find(true, self.len(), |count, _| count)?;
+        let (idx, _) = self.find::<Count>(true, self.len())?;
 
-        return Some(ElemIdx(idx));
+        return Some(self.elem_idx(idx));
     }
 
     #[inline]
     pub fn add(&mut self, element: T) -> ElemIdx {
-        return self.insert(self.nodes[self.root.get()].count, element);
+        return self.insert(self.nodes.count(self.root), element);
     }
 
     pub fn count_until(&self, index: impl BTreeIdx<T>) -> Option<usize> {
-        return self.sum_until(index, |c, _| c);
+        return self.sum_until::<Count>(index);
     }
 }
@@ -0,0 +1,368 @@
+use crate::metric::*;
+use crate::nodes::*;
+use crate::traits::*;
+use crate::tree::*;
+use crate::util::*;
+use alloc::vec::Vec;
+
+// Points at an element, and keeps track of everything before it. Moving to a
+// neighbor only touches the levels of the tree that actually change, so
+// walking the tree with a cursor is amortized O(1) per step, and seeking to a
+// nearby key only goes up as far as it needs to.
+//
+// A cursor can also sit just past the last element, where `get` returns None
+// and the running info is the info of the whole tree.
+pub struct BTreeCursor<'a, T, const B: usize = DEFAULT_B>
+where
+    T: BTreeItem,
+{
+    tree: &'a BTree<T, B>,
+    // One frame per level, starting at the root
+    path: Vec<Frame<T::Info>>,
+}
+
+// `count` and `info` are everything before `kid`, across the whole tree
+#[derive(Clone, Copy)]
+struct Frame<Info> {
+    node: Idx,
+    kid: usize,
+    count: usize,
+    info: Info,
+}
+
+impl<T, const B: usize> BTree<T, B>
+where
+    T: BTreeItem,
+{
+    pub fn cursor(&self) -> BTreeCursor<'_, T, B> {
+        let mut cursor = BTreeCursor {
+            tree: self,
+            path: Vec::with_capacity(self.levels + 1),
+        };
+
+        cursor.path.push(Frame {
+            node: self.root,
+            kid: 0,
+            count: 0,
+            info: Default::default(),
+        });
+        cursor.descend_first();
+
+        return cursor;
+    }
+
+    pub fn cursor_at(&self, index: impl BTreeIdx<T>) -> Option<BTreeCursor<'_, T, B>> {
+        let idx = index.get(self)?;
+
+        let mut cursor = self.cursor();
+        cursor.path.clear();
+
+        let mut node = self.element_parents[idx.get()];
+        let mut kid = idx;
+        loop {
+            let kids = self.nodes.kids(node);
+            let position = kids.iter().position(|k| k == kid).unwrap();
+            cursor.path.push(Frame {
+                node,
+                kid: position,
+                count: 0,
+                info: Default::default(),
+            });
+
+            kid = node;
+            node = match self.nodes.parent(node) {
+                Some(parent) => parent,
+                None => break,
+            };
+        }
+
+        cursor.path.reverse();
+        for level in 0..cursor.path.len() {
+            cursor.recompute(level);
+        }
+
+        return Some(cursor);
+    }
+}
+
+impl<'a, T, const B: usize> BTreeCursor<'a, T, B>
+where
+    T: BTreeItem,
+{
+    pub fn get(&self) -> Option<&'a T> {
+        let frame = self.leaf();
+        let kids = self.tree.nodes.kids(frame.node);
+        if frame.kid >= kids.len() {
+            return None;
+        }
+
+        return Some(&self.tree.elements[kids[frame.kid].get()]);
+    }
+
+    pub fn elem_idx(&self) -> Option<ElemIdx> {
+        let frame = self.leaf();
+        let kids = self.tree.nodes.kids(frame.node);
+        if frame.kid >= kids.len() {
+            return None;
+        }
+
+        return Some(self.tree.elem_idx(kids[frame.kid]));
+    }
+
+    // Number of elements before the current one
+    pub fn index(&self) -> usize {
+        return self.leaf().count;
+    }
+
+    // Combined info of all of the elements before the current one
+    pub fn info(&self) -> T::Info {
+        return self.leaf().info;
+    }
+
+    // Position of the current element in terms of `M`
+    pub fn offset<M>(&self) -> usize
+    where
+        M: Metric<T>,
+    {
+        let frame = self.leaf();
+        return M::measure(frame.count, frame.info);
+    }
+
+    // Moves to the next element. Returns false if there's no next element, in
+    // which case the cursor ends up past the end.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> bool {
+        let tree = self.tree;
+        let leaf = self.path.len() - 1;
+        let kid_count = tree.nodes.kids(self.path[leaf].node).len();
+        if self.path[leaf].kid >= kid_count {
+            return false;
+        }
+
+        self.step_over(leaf);
+        if self.path[leaf].kid < kid_count {
+            return true;
+        }
+
+        let mut level = leaf;
+        while level > 0 {
+            level -= 1;
+
+            let frame = self.path[level];
+            if frame.kid + 1 < tree.nodes.kids(frame.node).len() {
+                self.path.truncate(level + 1);
+                self.step_over(level);
+                self.descend_first();
+                return true;
+            }
+        }
+
+        // Leave the leaf frame past its last kid, with the info of everything
+        return false;
+    }
+
+    // Moves to the previous element. Returns false if there's no previous
+    // element, in which case the cursor doesn't move.
+    pub fn prev(&mut self) -> bool {
+        let mut level = self.path.len();
+        while level > 0 {
+            level -= 1;
+
+            if self.path[level].kid > 0 {
+                self.path.truncate(level + 1);
+                self.path[level].kid -= 1;
+                self.recompute(level);
+                self.descend_last();
+                return true;
+            }
+        }
+
+        return false;
+    }
+
+    // Moves to the element that contains `key`, like `BTree::key`, and returns
+    // the remainder of `key` inside of that element. If there's no such
+    // element, the cursor ends up past the end.
+    pub fn seek<M>(&mut self, key: usize) -> Option<usize>
+    where
+        M: Metric<T>,
+    {
+        let tree = self.tree;
+
+        // Go up until we're in a node that contains the key
+        let mut level = self.path.len() - 1;
+        while level > 0 {
+            let parent = self.path[level - 1];
+            let begin = M::measure(parent.count, parent.info);
+            let (count, info) = self.kid_measure(level - 1);
+            let end = begin + M::measure(count, info);
+            if begin <= key && key < end {
+                break;
+            }
+
+            level -= 1;
+        }
+
+        self.path.truncate(level + 1);
+        self.path[level].kid = 0;
+        self.recompute(level);
+
+        // Then go back down, like `BTree::find`
+        let mut remaining = key - self.offset_at::<M>(level);
+        loop {
+            let node = self.path[level].node;
+            let kid_count = tree.nodes.kids(node).len();
+
+            while self.path[level].kid < kid_count {
+                let value = self.kid_value::<M>(level);
+                if remaining < value {
+                    break;
+                }
+
+                remaining -= value;
+                self.step_over(level);
+            }
+
+            if self.path[level].kid == kid_count {
+                self.path.truncate(1);
+                self.path[0].kid = tree.nodes.kids(tree.root).len();
+                self.recompute(0);
+                self.descend_last_past_end();
+                return None;
+            }
+
+            if tree.nodes.is_leaf(node) {
+                return Some(remaining);
+            }
+
+            let frame = self.path[level];
+            self.path.push(Frame {
+                node: tree.nodes.kids(node)[frame.kid],
+                kid: 0,
+                count: frame.count,
+                info: frame.info,
+            });
+            level += 1;
+        }
+    }
+
+    fn leaf(&self) -> &Frame<T::Info> {
+        return self.path.last().unwrap();
+    }
+
+    fn offset_at<M>(&self, level: usize) -> usize
+    where
+        M: Metric<T>,
+    {
+        let frame = &self.path[level];
+        return M::measure(frame.count, frame.info);
+    }
+
+    // Measure of the kid that the frame at `level` points to
+    fn kid_value<M>(&self, level: usize) -> usize
+    where
+        M: Metric<T>,
+    {
+        let (count, info) = self.kid_measure(level);
+        return M::measure(count, info);
+    }
+
+    // Count and info of the kid that the frame at `level` points to
+    fn kid_measure(&self, level: usize) -> (usize, T::Info) {
+        let frame = &self.path[level];
+        return self.tree.nodes.kids(frame.node).summary(frame.kid);
+    }
+
+    fn step_over(&mut self, level: usize) {
+        let (count, info) = self.kid_measure(level);
+        let frame = &mut self.path[level];
+        frame.count += count;
+        frame.info = frame.info.add(info);
+        frame.kid += 1;
+    }
+
+    // Recomputes the running totals for the frame at `level` from the frame
+    // above it
+    fn recompute(&mut self, level: usize) {
+        let (mut count, mut info) = match level {
+            0 => (0, T::Info::default()),
+            _ => (self.path[level - 1].count, self.path[level - 1].info),
+        };
+
+        let frame = self.path[level];
+        let kids = self.tree.nodes.kids(frame.node);
+        for (kid_count, kid_info) in kids.summaries().take(frame.kid) {
+            count += kid_count;
+            info = info.add(kid_info);
+        }
+
+        let frame = &mut self.path[level];
+        frame.count = count;
+        frame.info = info;
+    }
+
+    fn descend_first(&mut self) {
+        loop {
+            let frame = *self.leaf();
+            let (nodes, node) = (&self.tree.nodes, frame.node);
+            if nodes.is_leaf(node) {
+                return;
+            }
+
+            self.path.push(Frame {
+                node: nodes.kids(node)[frame.kid],
+                kid: 0,
+                count: frame.count,
+                info: frame.info,
+            });
+        }
+    }
+
+    fn descend_last(&mut self) {
+        loop {
+            let frame = *self.leaf();
+            let (nodes, node) = (&self.tree.nodes, frame.node);
+            if nodes.is_leaf(node) {
+                return;
+            }
+
+            let kid = nodes.kids(node)[frame.kid];
+            let kid_count = self.tree.nodes.kids(kid).len();
+            self.path.push(Frame {
+                node: kid,
+                kid: kid_count - 1,
+                count: 0,
+                info: Default::default(),
+            });
+
+            self.recompute(self.path.len() - 1);
+        }
+    }
+
+    // Like `descend_last`, but the root frame is already past its last kid
+    fn descend_last_past_end(&mut self) {
+        let root = self.path[0];
+        if self.tree.nodes.is_leaf(root.node) {
+            return;
+        }
+
+        self.path[0].kid -= 1;
+        self.recompute(0);
+        self.descend_last();
+
+        let leaf = self.path.len() - 1;
+        self.step_over(leaf);
+    }
+}
+
+impl<'a, T, const B: usize> Clone for BTreeCursor<'a, T, B>
+where
+    T: BTreeItem,
+{
+    fn clone(&self) -> Self {
+        return Self {
+            tree: self.tree,
+            path: self.path.clone(),
+        };
+    }
+}
@@ -0,0 +1,176 @@
+use crate::traits::*;
+use crate::tree::*;
+use crate::util::*;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{Debug, Write};
+
+// Debug output for the structure of the tree, meant to be attached to bug
+// reports. The walk doesn't trust anything it reads, so a corrupted tree still
+// gets dumped as far as it can be; nodes that `check_node` complains about get
+// flagged with the reason.
+impl<T, const B: usize> BTree<T, B>
+where
+    T: BTreeItem,
+    T::Info: Debug + PartialEq,
+{
+    /// The whole tree as indented text, one node per line, with kids indented
+    /// under their parent.
+    pub fn dump_tree(&self) -> String {
+        let mut out = String::new();
+        self.write_tree(&mut out).unwrap();
+        return out;
+    }
+
+    /// The whole tree in Graphviz's dot format. Nodes that break an invariant
+    /// are red, and so are parent links that don't match the tree.
+    pub fn dump_dot(&self) -> String {
+        let mut out = String::new();
+        self.write_dot(&mut out).unwrap();
+        return out;
+    }
+
+    fn write_tree(&self, out: &mut String) -> core::fmt::Result {
+        writeln!(
+            out,
+            "BTree len={} levels={} root={:?}",
+            self.len(),
+            self.levels,
+            self.root
+        )?;
+
+        for (node, depth) in self.dump_order() {
+            let indent = 2 * (depth + 1);
+            let node_ref = match self.nodes.row(node) {
+                Some(node_ref) => node_ref,
+                None => {
+                    writeln!(out, "{:indent$}!! node {:?}: node was freed", "", node)?;
+                    continue;
+                }
+            };
+
+            if let Err(reason) = self.check_node(node, depth) {
+                writeln!(out, "{:indent$}!! node {:?}: {}", "", node, reason)?;
+            }
+
+            let kind = if node_ref.is_leaf { "leaf" } else { "node" };
+            write!(
+                out,
+                "{:indent$}{} {:?} count={} info={:?}",
+                "", kind, node, node_ref.count, node_ref.info
+            )?;
+            write!(out, " parent={:?}", node_ref.parent)?;
+            match node_ref.is_leaf {
+                true => writeln!(out, " elements={:?}", node_ref.kids)?,
+                false => writeln!(out)?,
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn write_dot(&self, out: &mut String) -> core::fmt::Result {
+        writeln!(out, "digraph btree {{")?;
+        writeln!(out, "    node [shape=box, fontname=monospace];")?;
+
+        for (node, depth) in self.dump_order() {
+            let node_ref = match self.nodes.row(node) {
+                Some(node_ref) => node_ref,
+                None => {
+                    let label = "node was freed";
+                    writeln!(
+                        out,
+                        "    n{:?} [label=\"{:?}\\n{}\", color=red];",
+                        node, node, label
+                    )?;
+                    continue;
+                }
+            };
+
+            let mut label = String::new();
+            let kind = if node_ref.is_leaf { "leaf" } else { "node" };
+            write!(
+                label,
+                "{} {:?}\ncount {}\ninfo {:?}",
+                kind, node, node_ref.count, node_ref.info
+            )?;
+            write!(label, "\nparent {:?}", node_ref.parent)?;
+            if node_ref.is_leaf {
+                write!(label, "\nelements {:?}", node_ref.kids)?;
+            }
+
+            let check = self.check_node(node, depth);
+            if let Err(reason) = check {
+                write!(label, "\n!! {}", reason)?;
+            }
+
+            write!(out, "    n{:?} [label=\"{}\"", node, DotEscape(&label))?;
+            match check {
+                Ok(()) => writeln!(out, "];")?,
+                Err(_) => writeln!(out, ", color=red, fontcolor=red];")?,
+            }
+
+            if node_ref.is_leaf {
+                continue;
+            }
+
+            for kid in &node_ref.kids {
+                writeln!(out, "    n{:?} -> n{:?};", node, kid)?;
+
+                let parent = self.nodes.row(kid).and_then(|kid| kid.parent);
+                if let Some(parent) = parent.filter(|&parent| parent != node) {
+                    writeln!(
+                        out,
+                        "    n{:?} -> n{:?} [style=dashed, color=red];",
+                        kid, parent
+                    )?;
+                }
+            }
+        }
+
+        writeln!(out, "}}")?;
+        return Ok(());
+    }
+
+    // Pre-order, so kids come right after their parent. Depth is capped at
+    // `levels`, so a cycle in a corrupted tree can't make this loop forever.
+    fn dump_order(&self) -> Vec<(Idx, usize)> {
+        let mut order = Vec::new();
+        let mut stack = Vec::with_capacity(self.levels * B + 1);
+        stack.push((self.root, 0));
+
+        while let Some((node, depth)) = stack.pop() {
+            order.push((node, depth));
+
+            let node_ref = match self.nodes.row(node) {
+                Some(node_ref) => node_ref,
+                None => continue,
+            };
+
+            if !node_ref.is_leaf && depth < self.levels {
+                stack.extend(node_ref.kids.iter().rev().map(|kid| (kid, depth + 1)));
+            }
+        }
+
+        return order;
+    }
+}
+
+// Escapes a label for use inside of a quoted dot string, turning newlines into
+// dot's own line breaks
+struct DotEscape<'a>(&'a str);
+
+impl core::fmt::Display for DotEscape<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                c => f.write_char(c)?,
+            }
+        }
+
+        return Ok(());
+    }
+}
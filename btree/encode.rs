@@ -0,0 +1,279 @@
+use crate::traits::*;
+use crate::tree::*;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// A small binary format for trees, so that we don't have to pull in serde.
+// Integers are LEB128 varints, which keeps small numbers small; everything
+// else is built out of those and raw bytes. There's no schema in the output,
+// so both ends have to agree on the element type.
+//
+// A tree is the format version, then the element count, then the elements in
+// order. The nodes aren't stored at all, since bulk loading rebuilds them in
+// O(n) anyways.
+const FORMAT_VERSION: u8 = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    UnknownVersion(u8),
+    // The bytes were there, but they didn't make sense
+    Invalid(&'static str),
+    // Everything decoded fine, but there was more input after it
+    TrailingBytes(usize),
+}
+
+impl core::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        return match self {
+            Self::UnexpectedEnd => write!(f, "input ended early"),
+            Self::UnknownVersion(version) => write!(f, "unknown format version {}", version),
+            Self::Invalid(reason) => write!(f, "invalid input: {}", reason),
+            Self::TrailingBytes(count) => write!(f, "{} extra bytes after the end", count),
+        };
+    }
+}
+
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+pub trait Decode: Sized {
+    // Reads a value off of the front of `input`, and moves `input` past it
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+impl<T, const B: usize> BTree<T, B>
+where
+    T: BTreeItem,
+{
+    pub fn encode(&self) -> Vec<u8>
+    where
+        T: Encode,
+    {
+        let mut out = Vec::new();
+        FORMAT_VERSION.encode(&mut out);
+        self.len().encode(&mut out);
+        for elem in self.iter() {
+            elem.encode(&mut out);
+        }
+
+        return out;
+    }
+
+    /// Decodes every element before building anything, so bad input never
+    /// results in a half-built tree.
+    pub fn decode(mut input: &[u8]) -> Result<Self, DecodeError>
+    where
+        T: Decode,
+    {
+        let version = u8::decode(&mut input)?;
+        if version != FORMAT_VERSION {
+            return Err(DecodeError::UnknownVersion(version));
+        }
+
+        // Elements almost always take at least a byte, so this keeps a bogus
+        // count from making us allocate a huge amount up front
+        let len = usize::decode(&mut input)?;
+        let mut elements = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            elements.push(T::decode(&mut input)?);
+        }
+
+        if !input.is_empty() {
+            return Err(DecodeError::TrailingBytes(input.len()));
+        }
+
+        return Ok(Self::build(elements));
+    }
+}
+
+fn take<'a>(input: &mut &'a [u8], count: usize) -> Result<&'a [u8], DecodeError> {
+    if input.len() < count {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+
+    let (taken, rest) = input.split_at(count);
+    *input = rest;
+    return Ok(taken);
+}
+
+impl Encode for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl Decode for u8 {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        return Ok(take(input, 1)?[0]);
+    }
+}
+
+macro_rules! varint {
+    ($($ty:ty),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    let mut value = *self;
+                    while value >= 0x80 {
+                        out.push(value as u8 | 0x80);
+                        value >>= 7;
+                    }
+
+                    out.push(value as u8);
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+                    let mut value: $ty = 0;
+                    let mut shift = 0;
+                    loop {
+                        let byte = u8::decode(input)?;
+                        let bits = (byte & 0x7f) as $ty;
+                        if shift >= <$ty>::BITS || (bits << shift) >> shift != bits {
+                            return Err(DecodeError::Invalid("varint is too big"));
+                        }
+
+                        value |= bits << shift;
+                        shift += 7;
+
+                        if byte & 0x80 == 0 {
+                            return Ok(value);
+                        }
+                    }
+                }
+            }
+        )*
+    };
+}
+
+varint!(u16, u32, u64, usize);
+
+// Zigzag encoded, so that small negative numbers stay small
+macro_rules! signed_varint {
+    ($($ty:ty => $unsigned:ty),*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    let value = ((*self << 1) ^ (*self >> (<$ty>::BITS - 1))) as $unsigned;
+                    value.encode(out);
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+                    let value = <$unsigned>::decode(input)?;
+                    return Ok((value >> 1) as $ty ^ -((value & 1) as $ty));
+                }
+            }
+        )*
+    };
+}
+
+signed_varint!(i16 => u16, i32 => u32, i64 => u64, isize => usize);
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl Decode for bool {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        return match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::Invalid("bool wasn't 0 or 1")),
+        };
+    }
+}
+
+impl Encode for char {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u32).encode(out);
+    }
+}
+
+impl Decode for char {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let value = u32::decode(input)?;
+        return char::from_u32(value)
+            .ok_or(DecodeError::Invalid("char isn't a unicode scalar value"));
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_str().encode(out);
+    }
+}
+
+impl Decode for String {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = usize::decode(input)?;
+        let bytes = take(input, len)?;
+        let text =
+            core::str::from_utf8(bytes).map_err(|_| DecodeError::Invalid("string isn't UTF-8"))?;
+        return Ok(String::from(text));
+    }
+}
+
+impl<T> Encode for Option<T>
+where
+    T: Encode,
+{
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.is_some().encode(out);
+        if let Some(value) = self {
+            value.encode(out);
+        }
+    }
+}
+
+impl<T> Decode for Option<T>
+where
+    T: Decode,
+{
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        return match bool::decode(input)? {
+            true => Ok(Some(T::decode(input)?)),
+            false => Ok(None),
+        };
+    }
+}
+
+impl<T> Encode for Vec<T>
+where
+    T: Encode,
+{
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        for value in self {
+            value.encode(out);
+        }
+    }
+}
+
+impl<T> Decode for Vec<T>
+where
+    T: Decode,
+{
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = usize::decode(input)?;
+        let mut values = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            values.push(T::decode(input)?);
+        }
+
+        return Ok(values);
+    }
+}
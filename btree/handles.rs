@@ -0,0 +1,133 @@
+use crate::arena::*;
+use crate::chunks::*;
+use crate::traits::*;
+use crate::util::*;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+// The layer of indirection between an ElemIdx and the slot its element lives
+// in. Elements are free to move to other slots, as long as their entry here
+// is kept up to date. Freeing an entry bumps its generation, so any ElemIdx
+// that still refers to it stops resolving instead of pointing at whatever
+// element reuses the entry later.
+//
+// Every table also gets its own owner id, which goes into each ElemIdx, so
+// that an ElemIdx from one table can't resolve to an element in another one
+// that happens to use the same entry.
+pub(crate) struct Handles {
+    slots: Arena<Idx>,
+    // Indexed by handle slot, including freed ones
+    generations: ChunkVec<u32>,
+    owner: u32,
+}
+
+static NEXT_OWNER: AtomicU32 = AtomicU32::new(0);
+
+fn next_owner() -> u32 {
+    return NEXT_OWNER.fetch_add(1, Ordering::Relaxed);
+}
+
+impl Handles {
+    pub fn new() -> Self {
+        return Self::with_capacity(0);
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        return Self {
+            slots: Arena::with_capacity(capacity),
+            generations: ChunkVec::with_capacity(capacity),
+            owner: next_owner(),
+        };
+    }
+
+    // A table of `len` handles, where handle `i` points at slot `i`
+    pub fn sequential(len: usize) -> Self {
+        return Self {
+            slots: (0..len).map(Idx::new).collect(),
+            generations: core::iter::repeat_n(0, len).collect(),
+            owner: next_owner(),
+        };
+    }
+
+    pub fn alloc(&mut self, elem: Idx) -> Idx {
+        let handle = self.slots.alloc(elem);
+        if handle.get() == self.generations.len() {
+            self.generations.push(0);
+        }
+
+        return handle;
+    }
+
+    pub fn free(&mut self, handle: Idx) {
+        self.slots.free(handle);
+
+        let generation = &mut self.generations[handle.get()];
+        *generation = generation.wrapping_add(1);
+    }
+
+    // Points `handle` at the new slot of an element that got moved
+    pub fn set(&mut self, handle: Idx, elem: Idx) {
+        self.slots[handle.get()] = elem;
+    }
+
+    pub fn resolve(&self, idx: ElemIdx) -> Option<Idx> {
+        if idx.owner != self.owner {
+            return None;
+        }
+
+        if self.generations.get(idx.handle.get()) != Some(&idx.generation) {
+            return None;
+        }
+
+        // A snapshot can be handed a handle that the live tree made after the
+        // snapshot was taken, in an entry that was still free back then
+        return self.slots.get(idx.handle.get()).copied();
+    }
+
+    // What each live handle costs, across all of the columns
+    pub const ROW_BYTES: usize = core::mem::size_of::<Slot<Idx>>() + core::mem::size_of::<u32>();
+
+    pub fn allocated_bytes(&self) -> usize {
+        return self.slots.allocated_bytes() + self.generations.allocated_bytes();
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.slots.shrink_to_fit();
+        self.generations.shrink_to_fit();
+    }
+
+    #[cfg(test)]
+    pub fn shared_chunks(&self) -> usize {
+        return self.slots.shared_chunks() + self.generations.shared_chunks();
+    }
+
+    pub fn elem_idx(&self, handle: Idx) -> ElemIdx {
+        return ElemIdx {
+            handle,
+            generation: self.generations[handle.get()],
+            owner: self.owner,
+        };
+    }
+
+    // Makes a copy that shares its storage and its owner with this one, so
+    // ElemIdx's resolve the same way in both. That's only safe when one of the
+    // copies never allocates another handle; otherwise both could hand out
+    // the same ElemIdx for different elements.
+    pub fn share(&self) -> Self {
+        return Self {
+            slots: self.slots.share(),
+            generations: self.generations.share(),
+            owner: self.owner,
+        };
+    }
+}
+
+// Clones are free to allocate handles of their own, so they get a new owner,
+// and none of the original's ElemIdx's work with them.
+impl Clone for Handles {
+    fn clone(&self) -> Self {
+        return Self {
+            owner: next_owner(),
+            ..self.share()
+        };
+    }
+}
@@ -0,0 +1,233 @@
+use crate::metric::*;
+use crate::nodes::*;
+use crate::traits::*;
+use crate::tree::*;
+use crate::util::*;
+use core::ops::RangeBounds;
+
+// Walks the leaf level directly. Each step is usually just a bump of the kid
+// position; crossing into the next leaf walks up to the closest ancestor with
+// a sibling and back down, which amortizes out to O(1) per element.
+pub struct Iter<'a, T, const B: usize = DEFAULT_B>
+where
+    T: BTreeItem,
+{
+    tree: &'a BTree<T, B>,
+    front: LeafPos,
+    back: LeafPos,
+    remaining: usize,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct LeafPos {
+    pub(crate) leaf: Idx,
+    pub(crate) kid: usize,
+}
+
+impl<T, const B: usize> BTree<T, B>
+where
+    T: BTreeItem,
+{
+    pub fn iter(&self) -> Iter<'_, T, B> {
+        return self.range(..);
+    }
+
+    pub fn iter_from(&self, index: impl BTreeIdx<T>) -> Option<Iter<'_, T, B>> {
+        let idx = index.get(self)?;
+        let begin = self.count_until(idx)?;
+
+        return Some(self.range(begin..));
+    }
+
+    /// Iterates starting at the element that contains `key`, like `key`. Also
+    /// returns the remainder of `key` within that first element.
+    pub fn iter_from_key<M>(&self, key: usize) -> Option<(Iter<'_, T, B>, usize)>
+    where
+        M: Metric<T>,
+    {
+        let (idx, remainder) = self.key_idx::<M>(key)?;
+        return Some((self.iter_from(idx)?, remainder));
+    }
+
+    /// Iterates starting at the element that contains `key`, like `key_leq`.
+    /// Also returns the remainder of `key` within that first element.
+    pub fn iter_from_key_leq<M>(&self, key: usize) -> Option<(Iter<'_, T, B>, usize)>
+    where
+        M: Metric<T>,
+    {
+        let (idx, remainder) = self.key_leq_idx::<M>(key)?;
+        return Some((self.iter_from(idx)?, remainder));
+    }
+
+    pub fn range(&self, range: impl RangeBounds<usize>) -> Iter<'_, T, B> {
+        let (begin, end) = resolve_range(range, self.len());
+        let (front, back) = match begin == end {
+            true => {
+                let pos = self.first_pos(self.root);
+                (pos, pos)
+            }
+            false => (self.pos_for_index(begin), self.pos_for_index(end - 1)),
+        };
+
+        return Iter {
+            tree: self,
+            front,
+            back,
+            remaining: end - begin,
+        };
+    }
+
+    pub(crate) fn pos_for_index(&self, index: usize) -> LeafPos {
+        let (idx, _) = self.find::<Count>(false, index).unwrap();
+        return self.leaf_pos(idx);
+    }
+
+    pub(crate) fn leaf_pos(&self, idx: Idx) -> LeafPos {
+        let leaf = self.element_parents[idx.get()];
+        let kid = self.nodes.kids(leaf).iter().position(|k| k == idx).unwrap();
+
+        return LeafPos { leaf, kid };
+    }
+
+    pub(crate) fn first_pos(&self, mut node: Idx) -> LeafPos {
+        while !self.nodes.is_leaf(node) {
+            node = self.nodes.kids(node)[0];
+        }
+
+        return LeafPos { leaf: node, kid: 0 };
+    }
+
+    pub(crate) fn last_pos(&self, mut node: Idx) -> LeafPos {
+        while !self.nodes.is_leaf(node) {
+            let kids = self.nodes.kids(node);
+            node = kids[kids.len() - 1];
+        }
+
+        let kid = self.nodes.kids(node).len().saturating_sub(1);
+        return LeafPos { leaf: node, kid };
+    }
+
+    pub(crate) fn next_pos(&self, pos: LeafPos) -> Option<LeafPos> {
+        let kids = self.nodes.kids(pos.leaf);
+        if pos.kid + 1 < kids.len() {
+            return Some(LeafPos {
+                leaf: pos.leaf,
+                kid: pos.kid + 1,
+            });
+        }
+
+        let mut node = pos.leaf;
+        while let Some(parent) = self.nodes.parent(node) {
+            let kids = self.nodes.kids(parent);
+            let index = kids.iter().position(|k| k == node).unwrap();
+            if index + 1 < kids.len() {
+                return Some(self.first_pos(kids[index + 1]));
+            }
+
+            node = parent;
+        }
+
+        return None;
+    }
+
+    pub(crate) fn prev_pos(&self, pos: LeafPos) -> Option<LeafPos> {
+        if pos.kid > 0 {
+            return Some(LeafPos {
+                leaf: pos.leaf,
+                kid: pos.kid - 1,
+            });
+        }
+
+        let mut node = pos.leaf;
+        while let Some(parent) = self.nodes.parent(node) {
+            let kids = self.nodes.kids(parent);
+            let index = kids.iter().position(|k| k == node).unwrap();
+            if index > 0 {
+                return Some(self.last_pos(kids[index - 1]));
+            }
+
+            node = parent;
+        }
+
+        return None;
+    }
+
+    #[inline]
+    pub(crate) fn elem_at(&self, pos: LeafPos) -> Idx {
+        return self.nodes.kids(pos.leaf)[pos.kid];
+    }
+}
+
+impl<'a, T, const B: usize> Clone for Iter<'a, T, B>
+where
+    T: BTreeItem,
+{
+    fn clone(&self) -> Self {
+        return Self {
+            tree: self.tree,
+            front: self.front,
+            back: self.back,
+            remaining: self.remaining,
+        };
+    }
+}
+
+impl<'a, T, const B: usize> Iterator for Iter<'a, T, B>
+where
+    T: BTreeItem,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let idx = self.tree.elem_at(self.front);
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            self.front = self.tree.next_pos(self.front).unwrap();
+        }
+
+        return Some(&self.tree.elements[idx.get()]);
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        return (self.remaining, Some(self.remaining));
+    }
+}
+
+impl<'a, T, const B: usize> DoubleEndedIterator for Iter<'a, T, B>
+where
+    T: BTreeItem,
+{
+    fn next_back(&mut self) -> Option<&'a T> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let idx = self.tree.elem_at(self.back);
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            self.back = self.tree.prev_pos(self.back).unwrap();
+        }
+
+        return Some(&self.tree.elements[idx.get()]);
+    }
+}
+
+impl<'a, T, const B: usize> ExactSizeIterator for Iter<'a, T, B> where T: BTreeItem {}
+
+impl<'a, T, const B: usize> core::iter::FusedIterator for Iter<'a, T, B> where T: BTreeItem {}
+
+impl<'a, T, const B: usize> IntoIterator for &'a BTree<T, B>
+where
+    T: BTreeItem,
+{
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T, B>;
+
+    fn into_iter(self) -> Iter<'a, T, B> {
+        return self.iter();
+    }
+}
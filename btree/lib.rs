@@ -1,13 +1,34 @@
 #![no_std]
 
+mod arena;
+mod bulk;
+mod check;
+mod chunks;
+mod compact;
 mod convenience;
+mod cursor;
+mod dump;
+mod encode;
+mod handles;
+mod iter;
+mod metric;
 mod nodes;
+mod snapshot;
+mod splice;
 mod traits;
 mod tree;
 mod util;
 
 extern crate alloc;
 
+pub use check::*;
+pub use compact::*;
+pub use cursor::*;
+pub use encode::*;
+pub use iter::*;
+pub use metric::*;
+pub use nodes::DEFAULT_B;
+pub use snapshot::*;
 pub use traits::*;
 pub use tree::*;
 
@@ -15,7 +36,7 @@ pub use tree::*;
 mod tests {
     use super::*;
 
-    #[derive(Clone, Copy, Default)]
+    #[derive(Clone, Copy, Default, PartialEq)]
     struct TestData(usize);
 
     impl core::fmt::Debug for TestData {
@@ -38,6 +59,32 @@ mod tests {
         }
     }
 
+    // Measures each element by its value
+    struct Value;
+
+    impl Metric<TestData> for Value {
+        fn measure(_count: usize, info: TestData) -> usize {
+            return info.0;
+        }
+    }
+
+    // Like `Value`, but every element is twice as wide
+    struct Doubled;
+
+    impl Metric<TestData> for Doubled {
+        fn measure(_count: usize, info: TestData) -> usize {
+            return info.0 * 2;
+        }
+
+        fn to_offset(_elem: &TestData, value: usize) -> usize {
+            return value / 2;
+        }
+
+        fn from_offset(_elem: &TestData, offset: usize) -> usize {
+            return offset * 2;
+        }
+    }
+
     const TREE_SIZE: usize = 1000;
 
     fn validate(mut tree: BTree<TestData>) {
@@ -60,11 +107,11 @@ mod tests {
                 next - 1,
             ];
             for key in test_cases.into_iter() {
-                let (value, remainder) = tree.key(key, |n| n.0).unwrap();
+                let (value, remainder) = tree.key::<Value>(key).unwrap();
                 assert_eq!(i, value.0);
                 assert_eq!(key - total, remainder);
 
-                let sum = tree.sum_until(i, |_, i| i.0).unwrap();
+                let sum = tree.sum_until::<Value>(i).unwrap();
                 assert_eq!(total, sum);
             }
 
@@ -98,4 +145,932 @@ mod tests {
 
         validate(tree);
     }
+
+    #[test]
+    fn iterators() {
+        let mut tree = BTree::new();
+        for i in 0..TREE_SIZE {
+            tree.add(TestData(i));
+        }
+
+        assert!(tree.iter().map(|d| d.0).eq(0..TREE_SIZE));
+        assert!(tree.iter().rev().map(|d| d.0).eq((0..TREE_SIZE).rev()));
+        assert!(tree.range(100..900).map(|d| d.0).eq(100..900));
+        assert_eq!(tree.range(400..400).count(), 0);
+        assert_eq!(tree.range(..).len(), TREE_SIZE);
+
+        let mut iter = tree.range(10..20);
+        let mut expected = 10..20;
+        while let Some(d) = iter.next() {
+            assert_eq!(d.0, expected.next().unwrap());
+            if let Some(d) = iter.next_back() {
+                assert_eq!(d.0, expected.next_back().unwrap());
+            }
+        }
+        assert_eq!(expected.len(), 0);
+
+        let idx = tree.get_idx(500).unwrap();
+        assert!(tree.iter_from(idx).unwrap().map(|d| d.0).eq(500..TREE_SIZE));
+        assert!(tree.iter_from(999).unwrap().map(|d| d.0).eq(999..TREE_SIZE));
+        assert!(tree.iter_from(TREE_SIZE).is_none());
+
+        // 0 + 1 + 2 + 3 = 6, so key 7 lands inside of TestData(4)
+        let (iter, remainder) = tree.iter_from_key::<Value>(7).unwrap();
+        assert_eq!(remainder, 1);
+        assert!(iter.map(|d| d.0).eq(4..TREE_SIZE));
+
+        let (iter, remainder) = tree.iter_from_key_leq::<Value>(6).unwrap();
+        assert_eq!(remainder, 3);
+        assert!(iter.map(|d| d.0).eq(3..TREE_SIZE));
+    }
+
+    #[test]
+    fn bulk_build() {
+        let tree: BTree<TestData> = (0..TREE_SIZE).map(TestData).collect();
+        assert_eq!(tree.len(), TREE_SIZE);
+        validate(tree);
+
+        for size in [0, 1, 6, 7, 36, 37, 100] {
+            let tree: BTree<TestData> = (0..size).map(TestData).collect();
+            assert_eq!(tree.len(), size);
+            assert!(tree.iter().map(|d| d.0).eq(0..size));
+        }
+
+        let mut tree = BTree::new();
+        tree.extend((0..TREE_SIZE / 2).map(TestData));
+        tree.extend((TREE_SIZE / 2..TREE_SIZE).map(TestData));
+        validate(tree);
+
+        // Extending with more than the tree already has doesn't cost the
+        // existing elements their handles
+        let mut tree: BTree<TestData> = (0..10).map(TestData).collect();
+        let handle = tree.get_idx(5).unwrap();
+        tree.extend((10..TREE_SIZE).map(TestData));
+        assert_eq!(tree.get(handle).unwrap().0, 5);
+        validate(tree);
+    }
+
+    #[test]
+    fn split_and_append() {
+        for at in [0, 1, 5, 6, 7, 36, 37, 500, 998, 999, TREE_SIZE] {
+            let mut tree: BTree<TestData> = (0..TREE_SIZE).map(TestData).collect();
+            let mut tail = tree.split_off(at);
+
+            assert_eq!(tree.len(), at);
+            assert_eq!(tail.len(), TREE_SIZE - at);
+            assert!(tree.iter().map(|d| d.0).eq(0..at));
+            assert!(tail.iter().map(|d| d.0).eq(at..TREE_SIZE));
+
+            tree.append(&mut tail);
+            assert_eq!(tail.len(), 0);
+            validate(tree);
+        }
+
+        // Trees of very different heights, in both orders
+        for size in [1, 3, 6, 7, 40, 300] {
+            let mut tree: BTree<TestData> = (0..size).map(TestData).collect();
+            let mut tail: BTree<TestData> = (size..TREE_SIZE).map(TestData).collect();
+            tree.append(&mut tail);
+            validate(tree);
+
+            let split = TREE_SIZE - size;
+            let mut tree: BTree<TestData> = (0..split).map(TestData).collect();
+            let mut tail: BTree<TestData> = (split..TREE_SIZE).map(TestData).collect();
+            tree.append(&mut tail);
+            validate(tree);
+        }
+
+        // Inserting after splitting still has to work
+        let mut tree: BTree<TestData> = (0..TREE_SIZE).map(TestData).collect();
+        let mut tail = tree.split_off(333);
+        tree.add(TestData(333));
+        tail.remove(0);
+        tree.append(&mut tail);
+        validate(tree);
+    }
+
+    #[test]
+    fn split_rebalances_cut() {
+        // Trees built by inserting have less evenly packed nodes, which makes
+        // it a lot more likely for a cut to leave a node with only one kid
+        let mut seed = 5usize;
+        let mut next = move |max: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            return (seed >> 33) % max;
+        };
+
+        for _ in 0..100 {
+            let mut tree = BTree::<TestData, 4>::default();
+            for i in 0..300 {
+                tree.insert(next(i + 1), TestData(i));
+            }
+
+            let tail = tree.split_off(next(299) + 1);
+            tree.check_invariants().unwrap();
+            tail.check_invariants().unwrap();
+        }
+    }
+
+    #[test]
+    fn drain_and_retain() {
+        for (begin, end) in [
+            (0, 0),
+            (0, 1),
+            (0, 500),
+            (0, TREE_SIZE),
+            (1, 999),
+            (37, 38),
+            (500, TREE_SIZE),
+        ] {
+            let mut tree: BTree<TestData> = (0..TREE_SIZE).map(TestData).collect();
+            let handles: alloc::vec::Vec<_> =
+                (0..TREE_SIZE).map(|i| tree.get_idx(i).unwrap()).collect();
+
+            let drained = tree.drain(begin..end);
+            assert!(drained.iter().map(|d| d.0).eq(begin..end));
+            assert!(tree
+                .iter()
+                .map(|d| d.0)
+                .eq((0..begin).chain(end..TREE_SIZE)));
+            tree.check_invariants().unwrap();
+
+            for (i, &handle) in handles.iter().enumerate() {
+                match (begin..end).contains(&i) {
+                    true => assert!(tree.get(handle).is_none()),
+                    false => assert_eq!(tree.get(handle).unwrap().0, i),
+                }
+            }
+
+            // Still has to be usable afterwards
+            tree.insert(begin, TestData(begin));
+            tree.add(TestData(TREE_SIZE));
+            tree.check_invariants().unwrap();
+        }
+
+        let mut tree: BTree<TestData> = (0..TREE_SIZE).map(TestData).collect();
+        let handles: alloc::vec::Vec<_> =
+            (0..TREE_SIZE).map(|i| tree.get_idx(i).unwrap()).collect();
+        tree.retain(|d| d.0 % 3 != 0);
+        assert!(tree
+            .iter()
+            .map(|d| d.0)
+            .eq((0..TREE_SIZE).filter(|i| i % 3 != 0)));
+        tree.check_invariants().unwrap();
+
+        for (i, &handle) in handles.iter().enumerate() {
+            match i % 3 {
+                0 => assert!(tree.get(handle).is_none()),
+                _ => assert_eq!(tree.get(handle).unwrap().0, i),
+            }
+        }
+
+        tree.retain(|_| false);
+        assert_eq!(tree.len(), 0);
+        assert_eq!(tree.levels, 0);
+        tree.check_invariants().unwrap();
+
+        tree.add(TestData(1));
+        tree.check_invariants().unwrap();
+
+        // Removing one long run leaves the nodes on either side of it alone,
+        // so they're still shared with the snapshot, along with the elements
+        // that are kept
+        let mut tree: BTree<TestData> = (0..TREE_SIZE * 100).map(TestData).collect();
+        let snapshot = tree.snapshot();
+        tree.retain(|d| d.0 < 1000 || d.0 >= 90_000);
+        tree.check_invariants().unwrap();
+        assert!(tree
+            .iter()
+            .map(|d| d.0)
+            .eq((0..1000).chain(90_000..100_000)));
+        assert!(tree.elements.shared_chunks() >= tree.len() / 64 - 2);
+        assert!(tree.nodes.shared_chunks() >= tree.len() / DEFAULT_B / 64);
+        assert!(snapshot.iter().map(|d| d.0).eq(0..TREE_SIZE * 100));
+
+        // Keeping everything doesn't write to anything
+        let snapshot = tree.snapshot();
+        let shared = (tree.elements.shared_chunks(), tree.nodes.shared_chunks());
+        tree.retain(|_| true);
+        assert_eq!(
+            (tree.elements.shared_chunks(), tree.nodes.shared_chunks()),
+            shared
+        );
+        drop(snapshot);
+
+        // Uneven nodes, with removals scattered all over the place
+        let mut seed = 7usize;
+        let mut next = move |max: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            return (seed >> 33) % max;
+        };
+
+        for round in 0..50 {
+            let mut tree = BTree::<TestData, 4>::default();
+            let mut model = alloc::vec::Vec::new();
+            for i in 0..300 {
+                let index = next(i + 1);
+                tree.insert(index, TestData(i));
+                model.insert(index, i);
+            }
+
+            let (begin, end) = (next(300), next(300));
+            let keep = |i: usize| match round % 3 {
+                0 => i.is_multiple_of(round + 2),
+                1 => !(begin..end).contains(&i),
+                _ => (begin..end).contains(&i) && i.is_multiple_of(2),
+            };
+
+            tree.retain(|d| keep(d.0));
+            model.retain(|&i| keep(i));
+            tree.check_invariants().unwrap();
+            assert!(tree.iter().map(|d| d.0).eq(model.iter().copied()));
+        }
+    }
+
+    #[test]
+    fn delete_heavy() {
+        let mut tree = BTree::new();
+        let mut seed = 12345usize;
+        let mut next = move |max: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            return (seed >> 33) % max;
+        };
+
+        // Every node other than the root has at least B / 2 kids, so the
+        // height, which is `levels + 1`, can't go past ceil(log_{B/2}(len)) + 1
+        let max_height = |len: usize| {
+            let (mut height, mut capacity) = (1, 1);
+            while capacity < len {
+                capacity *= DEFAULT_B / 2;
+                height += 1;
+            }
+
+            return height;
+        };
+
+        let mut model = alloc::vec::Vec::new();
+        for round in 0..20 {
+            for i in 0..TREE_SIZE {
+                let index = next(tree.len() + 1);
+                tree.insert(index, TestData(i));
+                model.insert(index, i);
+            }
+
+            tree.check_invariants().unwrap();
+
+            // Leave a few more behind each round so the tree keeps growing
+            for _ in 0..(TREE_SIZE - round * 10) {
+                let index = next(tree.len());
+                assert_eq!(tree.remove(index).unwrap().0, model.remove(index));
+                if tree.len() % 97 == 0 {
+                    tree.check_invariants().unwrap();
+                }
+            }
+
+            tree.check_invariants().unwrap();
+            assert!(tree.levels < max_height(tree.len()));
+            assert!(tree.iter().map(|d| d.0).eq(model.iter().copied()));
+        }
+
+        while tree.len() > 0 {
+            tree.remove(next(tree.len()));
+            tree.check_invariants().unwrap();
+            assert!(tree.levels < max_height(tree.len()));
+        }
+
+        assert_eq!(tree.levels, 0);
+
+        for at in [1, 10, 100, 500, 900, 999] {
+            let mut tree: BTree<TestData> = (0..TREE_SIZE).map(TestData).collect();
+            let tail = tree.split_off(at);
+            tree.check_invariants().unwrap();
+            tail.check_invariants().unwrap();
+        }
+    }
+
+    #[test]
+    fn stable_handles() {
+        let mut tree = BTree::new();
+        let handles: alloc::vec::Vec<_> = (0..TREE_SIZE).map(|i| tree.add(TestData(i))).collect();
+
+        // Remove every third element, including the last one
+        for i in (0..TREE_SIZE).rev().filter(|i| i % 3 == 0) {
+            assert_eq!(tree.remove(handles[i]).unwrap().0, i);
+        }
+
+        for _ in 0..TREE_SIZE {
+            tree.insert(tree.len() / 2, TestData(TREE_SIZE));
+        }
+
+        for (i, &handle) in handles.iter().enumerate() {
+            match i % 3 {
+                0 => {
+                    assert!(tree.get(handle).is_none());
+                    assert!(tree.remove(handle).is_none());
+                    assert!(tree.insert_after(handle, TestData(0)).is_none());
+                }
+                _ => assert_eq!(tree.get(handle).unwrap().0, i),
+            }
+        }
+
+        let handle = tree.get_idx(0).unwrap();
+        assert_eq!(handle, handles[1]);
+        let before = tree.insert_before(handle, TestData(0)).unwrap();
+        assert_eq!(tree.count_until(before), Some(0));
+        assert_eq!(tree.count_until(handle), Some(1));
+
+        // Elements in the bigger half of a split keep their handles, even when
+        // it's the half that gets returned; the ones in the smaller half get
+        // new handles
+        let mut tail = tree.split_off(tree.len() - 10);
+        assert_eq!(tree.get(handles[1]).unwrap().0, 1);
+        assert!(tree.get(handles[TREE_SIZE - 2]).is_none());
+        assert!(tail.get(handles[TREE_SIZE - 2]).is_none());
+
+        let tail_handle = tail.last_idx().unwrap();
+        assert_eq!(tail[tail_handle].0, TREE_SIZE - 2);
+        assert!(tree.get(tail_handle).is_none());
+
+        let mut head = tree;
+        let mut tree = head.split_off(10);
+        assert!(head.get(handles[1]).is_none());
+        assert_eq!(
+            tree.get(handles[TREE_SIZE / 2 + 2]).unwrap().0,
+            TREE_SIZE / 2 + 2
+        );
+
+        // Same for appending, in either order
+        tree.append(&mut tail);
+        assert_eq!(
+            tree.get(handles[TREE_SIZE / 2 + 2]).unwrap().0,
+            TREE_SIZE / 2 + 2
+        );
+        assert!(tree.get(tail_handle).is_none());
+
+        head.append(&mut tree);
+        assert_eq!(
+            head.get(handles[TREE_SIZE / 2 + 2]).unwrap().0,
+            TREE_SIZE / 2 + 2
+        );
+        assert_eq!(head.last_idx().map(|idx| head[idx].0), Some(TREE_SIZE - 2));
+        head.check_invariants().unwrap();
+
+        // Handles from one tree don't work in another
+        let other: BTree<TestData> = (0..TREE_SIZE).map(TestData).collect();
+        assert!(other.get(handles[1]).is_none());
+        assert!(head.get(other.get_idx(0).unwrap()).is_none());
+
+        // A snapshot doesn't know about handles made after it was taken, both
+        // when they reuse an entry that was already free in the snapshot, and
+        // when they're past the end of the snapshot's entries
+        let mut tree: BTree<TestData> = (0..10).map(TestData).collect();
+        tree.remove(0);
+        let snapshot = tree.snapshot();
+        let reused = tree.add(TestData(10));
+        let fresh = tree.add(TestData(11));
+        assert_eq!(reused.handle.get(), 0);
+        assert_eq!(fresh.handle.get(), 10);
+        assert_eq!(tree[reused].0, 10);
+        assert!(snapshot.get(reused).is_none());
+        assert!(snapshot.get(fresh).is_none());
+        assert!(snapshot.count_until(reused).is_none());
+    }
+
+    #[test]
+    fn uneven_splices() {
+        let mut tree: BTree<TestData> = (0..TREE_SIZE * 100).map(TestData).collect();
+        let snapshot = tree.snapshot();
+        let shared = |tree: &BTree<TestData>| {
+            return (
+                tree.elements.shared_chunks(),
+                tree.handles.shared_chunks(),
+                tree.nodes.shared_chunks(),
+            );
+        };
+
+        // Only the chunks that the small side was in, and the nodes along the
+        // cut, get written to; everything else is still shared with the
+        // snapshot
+        let touched = |before: (usize, usize, usize), after: (usize, usize, usize)| {
+            assert!(before.0 - after.0 <= 1);
+            assert!(before.1 - after.1 <= 2);
+            assert!(before.2 - after.2 <= 16);
+        };
+
+        let before = shared(&tree);
+        let mut end = tree.split_off(TREE_SIZE * 100 - 10);
+        touched(before, shared(&tree));
+
+        let before = shared(&tree);
+        let mut rest = tree.split_off(10);
+        touched(before, shared(&rest));
+        assert_eq!(tree.len(), 10);
+
+        let before = shared(&rest);
+        tree.append(&mut rest);
+        touched(before, shared(&tree));
+
+        let before = shared(&tree);
+        tree.append(&mut end);
+        touched(before, shared(&tree));
+
+        tree.check_invariants().unwrap();
+        assert!(tree.iter().map(|d| d.0).eq(0..TREE_SIZE * 100));
+        assert!(snapshot.iter().map(|d| d.0).eq(0..TREE_SIZE * 100));
+    }
+
+    #[test]
+    fn compaction() {
+        let mut tree = BTree::new();
+        let handles: alloc::vec::Vec<_> = (0..TREE_SIZE).map(|i| tree.add(TestData(i))).collect();
+        for i in (0..TREE_SIZE).filter(|i| i % 4 != 0) {
+            tree.remove(handles[i]);
+        }
+
+        let before = tree.memory_stats();
+        assert_eq!(before.elements, TREE_SIZE / 4);
+        let snapshot = tree.snapshot();
+
+        tree.compact();
+        tree.shrink_to_fit();
+        tree.check_invariants().unwrap();
+
+        let after = tree.memory_stats();
+        assert_eq!(after.elements, before.elements);
+        assert!(after.wasted_bytes < before.wasted_bytes / 4);
+        assert!(after.allocated_bytes < before.allocated_bytes);
+
+        // Siblings are contiguous, and the elements are in order
+        assert_eq!(tree.root.get(), 0);
+        let leaf = tree.first_pos(tree.root).leaf;
+        let kids = tree.nodes.kids(leaf);
+        assert!(kids.iter().map(|kid| kid.get()).eq(0..kids.len()));
+
+        for (i, &handle) in handles.iter().enumerate() {
+            match i % 4 {
+                0 => assert_eq!(tree.get(handle).unwrap().0, i),
+                _ => assert!(tree.get(handle).is_none()),
+            }
+        }
+
+        // The snapshot still has the tree from before
+        assert!(snapshot.iter().map(|d| d.0).eq((0..TREE_SIZE).step_by(4)));
+        assert_eq!(snapshot.get(handles[4]).unwrap().0, 4);
+
+        for i in 0..TREE_SIZE {
+            tree.insert(i % (tree.len() + 1), TestData(i));
+        }
+
+        tree.check_invariants().unwrap();
+        assert_eq!(tree.len(), TREE_SIZE + TREE_SIZE / 4);
+
+        let mut tree: BTree<TestData> = BTree::new();
+        tree.compact();
+        tree.shrink_to_fit();
+        assert_eq!(tree.memory_stats().nodes, 1);
+        tree.add(TestData(0));
+        tree.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn snapshots() {
+        let mut tree: BTree<TestData> = (0..TREE_SIZE * 10).map(TestData).collect();
+        let handle = tree.get_idx(5000).unwrap();
+        let snapshot = tree.snapshot();
+
+        let (node_chunks, elem_chunks) =
+            (tree.nodes.shared_chunks(), tree.elements.shared_chunks());
+        // Each node on the path gets its summary and its kids written to
+        tree.get_mut(5000, |d| d.0 += 1);
+        assert!(node_chunks - tree.nodes.shared_chunks() <= 2 * (tree.levels + 1));
+        assert_eq!(elem_chunks - tree.elements.shared_chunks(), 1);
+
+        let old = tree.snapshot();
+        tree.insert(0, TestData(0));
+        tree.remove(TREE_SIZE);
+        tree.edit_or_remove(1, |_| true);
+        tree.edit_or_remove(2, |d| {
+            d.0 = 0;
+            return false;
+        });
+
+        assert_eq!(snapshot.len(), TREE_SIZE * 10);
+        assert!(snapshot.iter().map(|d| d.0).eq(0..TREE_SIZE * 10));
+        assert_eq!(snapshot[handle].0, 5000);
+        assert_eq!(old[handle].0, 5001);
+        assert_eq!(tree[handle].0, 5001);
+
+        drop(snapshot);
+        drop(old);
+
+        tree.add(TestData(0));
+        assert_eq!(tree.len(), TREE_SIZE * 10);
+        assert_eq!(tree.elements.shared_chunks(), 0);
+
+        // A clone can add elements of its own, so handles from the original
+        // must not resolve in it, even when the two hand out the same entry
+        let mut clone = tree.clone();
+        assert!(clone.get(handle).is_none());
+        let handle = tree.add(TestData(98));
+        let cloned = clone.add(TestData(99));
+        assert_eq!(tree.get(handle).unwrap().0, 98);
+        assert_eq!(clone.get(cloned).unwrap().0, 99);
+        assert!(clone.get(handle).is_none());
+        assert!(tree.get(cloned).is_none());
+
+        // An entry that the tree frees and reuses after a snapshot still
+        // belongs to the old element as far as the snapshot is concerned
+        let snapshot = tree.snapshot();
+        tree.remove(handle);
+        let reused = tree.add(TestData(97));
+        assert_eq!(reused.handle, handle.handle);
+        assert_eq!(snapshot.get(handle).unwrap().0, 98);
+        assert!(snapshot.get(reused).is_none());
+    }
+
+    #[test]
+    fn range_info() {
+        let tree: BTree<TestData> = (0..TREE_SIZE).map(TestData).collect();
+        let sum = |begin: usize, end: usize| (begin..end).sum::<usize>();
+
+        for begin in (0..TREE_SIZE).step_by(37) {
+            for end in (begin..=TREE_SIZE).step_by(53) {
+                assert_eq!(tree.info_range(begin..end).0, sum(begin, end));
+            }
+        }
+
+        assert_eq!(tree.info_range(..).0, sum(0, TREE_SIZE));
+        assert_eq!(tree.info_range(10..=10).0, 10);
+        assert_eq!(tree.info_range(999..).0, 999);
+
+        // Elements 4, 5, and 6 cover keys 6..21; this range starts 2 into
+        // element 4 and ends 3 into element 6.
+        let (info, begin, end) = tree.info_key_range::<Value>(8..18).unwrap();
+        assert_eq!((info.0, begin, end), (15, 2, 3));
+
+        let (info, begin, end) = tree.info_key_range::<Value>(6..10).unwrap();
+        assert_eq!((info.0, begin, end), (4, 0, 4));
+
+        assert!(tree
+            .info_key_range::<Value>(0..sum(0, TREE_SIZE) + 1)
+            .is_none());
+    }
+
+    #[test]
+    fn cursors() {
+        let tree: BTree<TestData> = (0..TREE_SIZE).map(TestData).collect();
+        let sum = |end: usize| (0..end).sum::<usize>();
+
+        let mut cursor = tree.cursor();
+        for i in 0..TREE_SIZE {
+            assert_eq!(cursor.get().unwrap().0, i);
+            assert_eq!(cursor.index(), i);
+            assert_eq!(cursor.offset::<Value>(), sum(i));
+            assert_eq!(cursor.next(), i + 1 < TREE_SIZE);
+        }
+
+        assert!(cursor.get().is_none());
+        assert_eq!(cursor.index(), TREE_SIZE);
+        assert_eq!(cursor.info().0, sum(TREE_SIZE));
+        assert!(!cursor.next());
+
+        for i in (0..TREE_SIZE).rev() {
+            assert!(cursor.prev());
+            assert_eq!(cursor.get().unwrap().0, i);
+            assert_eq!(cursor.offset::<Value>(), sum(i));
+        }
+
+        assert!(!cursor.prev());
+        assert_eq!(cursor.index(), 0);
+
+        // Element 0 has no width, so key 0 lands in element 1
+        for i in (1..TREE_SIZE).step_by(7) {
+            let remainder = cursor.seek::<Value>(sum(i) + i / 2);
+            assert_eq!(remainder, Some(i / 2));
+            assert_eq!(cursor.get().unwrap().0, i);
+            assert_eq!(cursor.index(), i);
+        }
+
+        for i in (0..TREE_SIZE).rev().step_by(13) {
+            assert!(cursor.seek::<Count>(i).is_some());
+            assert_eq!(cursor.get().unwrap().0, i);
+            assert_eq!(cursor.offset::<Value>(), sum(i));
+        }
+
+        assert!(cursor.seek::<Value>(sum(TREE_SIZE)).is_none());
+        assert_eq!(cursor.index(), TREE_SIZE);
+        assert!(cursor.prev());
+        assert_eq!(cursor.get().unwrap().0, TREE_SIZE - 1);
+
+        let idx = tree.get_idx(500).unwrap();
+        let mut cursor = tree.cursor_at(idx).unwrap();
+        assert_eq!(cursor.index(), 500);
+        assert_eq!(cursor.info().0, sum(500));
+        assert!(cursor.next());
+        assert_eq!(cursor.elem_idx(), tree.get_idx(501));
+
+        let empty: BTree<TestData> = BTree::new();
+        let mut cursor = empty.cursor();
+        assert!(cursor.get().is_none());
+        assert!(!cursor.next());
+        assert!(!cursor.prev());
+        assert!(cursor.seek::<Count>(0).is_none());
+    }
+
+    #[test]
+    fn metrics() {
+        let tree: BTree<TestData> = (0..TREE_SIZE).map(TestData).collect();
+        let sum = |end: usize| (0..end).sum::<usize>();
+
+        for i in (0..TREE_SIZE).step_by(17) {
+            assert_eq!(tree.sum_until::<Count>(i), Some(i));
+            assert_eq!(tree.sum_until::<Value>(i), Some(sum(i)));
+            assert_eq!(tree.sum_until::<Doubled>(i), Some(sum(i) * 2));
+
+            assert_eq!(tree.key::<Count>(i).unwrap().0 .0, i);
+        }
+
+        // Keys on an element boundary go after the boundary with `key`, and
+        // before it with `key_leq`
+        let (elem, remainder) = tree.key::<Value>(sum(10)).unwrap();
+        assert_eq!((elem.0, remainder), (10, 0));
+        let (elem, remainder) = tree.key_leq::<Value>(sum(10)).unwrap();
+        assert_eq!((elem.0, remainder), (9, 9));
+
+        assert!(tree.key::<Value>(sum(TREE_SIZE)).is_none());
+        assert!(tree.key_leq::<Value>(sum(TREE_SIZE)).is_some());
+
+        for value in (0..=sum(TREE_SIZE)).step_by(1009) {
+            assert_eq!(tree.convert::<Value, Doubled>(value), Some(value * 2));
+            assert_eq!(tree.convert::<Doubled, Value>(value * 2), Some(value));
+        }
+
+        assert_eq!(
+            tree.convert::<Count, Value>(TREE_SIZE),
+            Some(sum(TREE_SIZE))
+        );
+        assert_eq!(tree.convert::<Value, Count>(sum(TREE_SIZE) + 1), None);
+        assert_eq!(BTree::<TestData>::new().convert::<Count, Value>(0), Some(0));
+    }
+
+    // Every element before the one being measured counts, including the ones
+    // that share its leaf
+    #[test]
+    fn count_until() {
+        let mut tree = BTree::new();
+        let handles: alloc::vec::Vec<_> = (0..3).map(|i| tree.add(TestData(i))).collect();
+        for (i, &handle) in handles.iter().enumerate() {
+            assert_eq!(tree.count_until(handle), Some(i));
+            assert_eq!(tree.sum_until::<Count>(i), Some(i));
+        }
+
+        let tree: BTree<TestData> = (0..TREE_SIZE).map(TestData).collect();
+        for i in 0..TREE_SIZE {
+            assert_eq!(tree.count_until(i), Some(i));
+        }
+    }
+
+    // Runs a random mix of every kind of edit against both a tree and a Vec,
+    // and checks that they agree
+    #[test]
+    fn model_based() {
+        run_model::<DEFAULT_B>();
+
+        // Small nodes split and merge all of the time
+        run_model::<4>();
+    }
+
+    fn run_model<const B: usize>() {
+        let mut tree = BTree::<TestData, B>::default();
+        let mut model: alloc::vec::Vec<usize> = alloc::vec::Vec::new();
+        let mut seed = 98765usize;
+        let mut next = move |max: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            return (seed >> 33) % max;
+        };
+
+        for step in 0..20 * TREE_SIZE {
+            // Grow to a decent size, then shrink back down, so that both
+            // splitting and merging get plenty of exercise
+            let growing = (step / (4 * TREE_SIZE)).is_multiple_of(2);
+            let len = model.len();
+
+            match next(if growing { 8 } else { 10 }) {
+                0..=2 => {
+                    let index = next(len + 1);
+                    tree.insert(index, TestData(step));
+                    model.insert(index, step);
+                }
+                3 if len > 0 => {
+                    let index = next(len);
+                    let handle = tree.get_idx(index).unwrap();
+                    tree.insert_before(handle, TestData(step)).unwrap();
+                    model.insert(index, step);
+                }
+                4 if len > 0 => {
+                    let index = next(len);
+                    let handle = tree.get_idx(index).unwrap();
+                    tree.insert_after(handle, TestData(step)).unwrap();
+                    model.insert(index + 1, step);
+                }
+                5 if len > 0 => {
+                    let index = next(len);
+                    let value = tree.get_mut(index, |d| {
+                        d.0 += 1;
+                        d.0
+                    });
+                    model[index] += 1;
+                    assert_eq!(value, Some(model[index]));
+                }
+                6 if len > 0 => {
+                    // Removes odd values, bumps even ones
+                    let index = next(len);
+                    let removed = tree.edit_or_remove(index, |d| {
+                        d.0 += 1;
+                        d.0.is_multiple_of(2)
+                    });
+
+                    model[index] += 1;
+                    match model[index].is_multiple_of(2) {
+                        true => assert_eq!(removed.map(|d| d.0), Some(model.remove(index))),
+                        false => assert!(removed.is_none()),
+                    }
+                }
+                _ if len > 0 => {
+                    let index = next(len);
+                    assert_eq!(tree.remove(index).map(|d| d.0), Some(model.remove(index)));
+                }
+                _ => {}
+            }
+
+            let len = model.len();
+            if step % 151 == 0 && len > 0 {
+                let begin = next(len);
+                let end = begin + next((len - begin).min(300) + 1);
+                let drained = tree.drain(begin..end);
+                assert!(drained.iter().map(|d| d.0).eq(model.drain(begin..end)));
+            }
+
+            if step % 997 == 0 {
+                let modulus = next(5) + 2;
+                tree.retain(|d| d.0 % modulus != 0);
+                model.retain(|v| v % modulus != 0);
+            }
+
+            assert_eq!(tree.len(), model.len());
+            if step % 37 == 0 {
+                tree.check_invariants().unwrap();
+                assert!(tree.iter().map(|d| d.0).eq(model.iter().copied()));
+                assert_eq!(tree.info().0, model.iter().sum::<usize>());
+            }
+        }
+
+        tree.check_invariants().unwrap();
+        assert!(tree.iter().map(|d| d.0).eq(model.iter().copied()));
+    }
+
+    #[test]
+    fn dumps() {
+        let mut tree: BTree<TestData, 4> = (0..20).map(TestData).collect();
+        let nodes = tree.memory_stats().nodes;
+
+        let text = tree.dump_tree();
+        assert_eq!(text.lines().count(), nodes + 1);
+        assert!(text.lines().nth(1).unwrap().starts_with("  node"));
+        assert!(!text.contains("!!"));
+
+        let dot = tree.dump_dot();
+        assert!(dot.starts_with("digraph btree {"));
+        assert_eq!(dot.matches(" -> ").count(), nodes - 1);
+        assert!(!dot.contains("red"));
+
+        let leaf = tree.last_pos(tree.root).leaf;
+        let (count, info) = tree.nodes.summary(leaf);
+        tree.nodes.set_summary(leaf, count, TestData(info.0 + 1));
+
+        let flagged = alloc::format!("!! node {:?}: cached info is wrong", leaf);
+        assert!(tree.dump_tree().contains(&flagged));
+        assert!(tree
+            .dump_dot()
+            .contains("!! cached info is wrong\", color=red"));
+    }
+
+    impl Encode for TestData {
+        fn encode(&self, out: &mut alloc::vec::Vec<u8>) {
+            self.0.encode(out);
+        }
+    }
+
+    impl Decode for TestData {
+        fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+            return Ok(Self(usize::decode(input)?));
+        }
+    }
+
+    #[test]
+    fn encoding() {
+        let tree: BTree<TestData> = (0..TREE_SIZE).map(|i| TestData(i * i)).collect();
+        let bytes = tree.encode();
+        let decoded = BTree::<TestData>::decode(&bytes).unwrap();
+        decoded.check_invariants().unwrap();
+        assert!(decoded.iter().eq(tree.iter()));
+
+        let empty = BTree::<TestData>::new().encode();
+        assert_eq!(empty, [1, 0]);
+        assert_eq!(BTree::<TestData>::decode(&empty).unwrap().len(), 0);
+
+        type Tree = BTree<TestData>;
+        assert_eq!(Tree::decode(&[]).err(), Some(DecodeError::UnexpectedEnd));
+        assert_eq!(
+            Tree::decode(&[2, 0]).err(),
+            Some(DecodeError::UnknownVersion(2))
+        );
+        assert_eq!(
+            Tree::decode(&bytes[..bytes.len() - 1]).err(),
+            Some(DecodeError::UnexpectedEnd)
+        );
+        assert_eq!(
+            Tree::decode(&[1, 1, 5, 0, 0]).err(),
+            Some(DecodeError::TrailingBytes(2))
+        );
+        assert_eq!(
+            Tree::decode(&[1, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f])
+                .err(),
+            Some(DecodeError::Invalid("varint is too big"))
+        );
+
+        // A huge count with no elements behind it just runs out of input
+        assert_eq!(
+            Tree::decode(&[1, 0xff, 0xff, 0xff, 0x7f]).err(),
+            Some(DecodeError::UnexpectedEnd)
+        );
+
+        let mut out = alloc::vec::Vec::new();
+        (-3i32).encode(&mut out);
+        assert_eq!(out, [5]);
+
+        let values: (
+            i64,
+            u16,
+            char,
+            alloc::string::String,
+            Option<bool>,
+            alloc::vec::Vec<u8>,
+        ) = (
+            i64::MIN,
+            300,
+            'é',
+            "héllo".into(),
+            Some(true),
+            alloc::vec![1, 2, 3],
+        );
+        let mut out = alloc::vec::Vec::new();
+        values.0.encode(&mut out);
+        values.1.encode(&mut out);
+        values.2.encode(&mut out);
+        values.3.encode(&mut out);
+        values.4.encode(&mut out);
+        values.5.encode(&mut out);
+
+        let mut input = &out[..];
+        assert_eq!(i64::decode(&mut input), Ok(values.0));
+        assert_eq!(u16::decode(&mut input), Ok(values.1));
+        assert_eq!(char::decode(&mut input), Ok(values.2));
+        assert_eq!(alloc::string::String::decode(&mut input), Ok(values.3));
+        assert_eq!(Option::<bool>::decode(&mut input), Ok(values.4));
+        assert_eq!(alloc::vec::Vec::<u8>::decode(&mut input), Ok(values.5));
+        assert!(input.is_empty());
+
+        assert_eq!(
+            char::decode(&mut &[0x80, 0xb0, 0x03][..]).err(),
+            Some(DecodeError::Invalid("char isn't a unicode scalar value"))
+        );
+        assert_eq!(
+            bool::decode(&mut &[2][..]).err(),
+            Some(DecodeError::Invalid("bool wasn't 0 or 1"))
+        );
+    }
+
+    #[test]
+    fn invariant_errors() {
+        let mut tree: BTree<TestData> = (0..TREE_SIZE).map(TestData).collect();
+        tree.check_invariants().unwrap();
+
+        let leaf = tree.first_pos(tree.root).leaf;
+        let (count, info) = tree.nodes.summary(leaf);
+        tree.nodes.set_summary(leaf, count + 1, info);
+        let error = tree.check_invariants().unwrap_err();
+        assert_eq!(
+            (error.node, error.reason),
+            (leaf.get(), "cached count is wrong")
+        );
+    }
 }
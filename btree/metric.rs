@@ -0,0 +1,81 @@
+use crate::traits::*;
+use crate::tree::*;
+
+// A coordinate system over the elements of a tree, e.g. characters, bytes,
+// or lines in a text buffer. Searching, summing and seeking all take a metric
+// instead of a closure, so that every caller agrees on what a key means.
+//
+// The measure of a node has to be the sum of the measures of the elements
+// under it, since the tree only ever looks at a node's cached count and info
+// when it's skipping over the node. Likewise, `measure_elem` has to agree with
+// `measure(1, elem.get_info())`, since searches only look at the info cached
+// for each element in its leaf.
+pub trait Metric<T>
+where
+    T: BTreeItem,
+{
+    // Measures a run of `count` elements with the combined info `info`
+    fn measure(count: usize, info: T::Info) -> usize;
+
+    fn measure_elem(elem: &T) -> usize {
+        return Self::measure(1, elem.get_info());
+    }
+
+    // Converts `value`, an offset inside of `elem` in this metric, to an
+    // offset in the element's own units, whatever those are (bytes for text,
+    // say). The defaults are for metrics that already use the element's
+    // units.
+    fn to_offset(elem: &T, value: usize) -> usize {
+        let _ = elem;
+        return value;
+    }
+
+    // The inverse of `to_offset`
+    fn from_offset(elem: &T, offset: usize) -> usize {
+        let _ = elem;
+        return offset;
+    }
+}
+
+// Plain element indices
+pub struct Count;
+
+impl<T> Metric<T> for Count
+where
+    T: BTreeItem,
+{
+    fn measure(count: usize, _info: T::Info) -> usize {
+        return count;
+    }
+}
+
+impl<T, const B: usize> BTree<T, B>
+where
+    T: BTreeItem,
+{
+    // Converts a position in terms of `From` to the same position in terms of
+    // `To`. Positions on the boundary between two elements are converted using
+    // the element before the boundary, so the end of the tree converts too.
+    pub fn convert<From, To>(&self, value: usize) -> Option<usize>
+    where
+        From: Metric<T>,
+        To: Metric<T>,
+    {
+        if value == 0 && self.len() == 0 {
+            return Some(0);
+        }
+
+        let (idx, remainder) = self.find::<From>(true, value)?;
+        let elem = &self.elements[idx.get()];
+        let before = self.sum_until::<To>(idx)?;
+
+        // The end of an element doesn't have to be a valid offset inside of
+        // it, e.g. for `Count`
+        if remainder == From::measure_elem(elem) {
+            return Some(before + To::measure_elem(elem));
+        }
+
+        let offset = From::to_offset(elem, remainder);
+        return Some(before + To::from_offset(elem, offset));
+    }
+}
@@ -1,13 +1,21 @@
+use crate::arena::*;
+use crate::chunks::*;
 use crate::traits::*;
 use crate::util::*;
 
-const B: usize = 6;
+// Wide enough that a search only visits a handful of levels in a big tree,
+// but narrow enough that scanning a node's kids and shifting them around on
+// insert stays cheap. See benches/btree.rs for how the other sizes compare.
+pub const DEFAULT_B: usize = 32;
 
 // We're using the trick from Basic algo with the combining lists thing! From
 // the 2-3 tree PSet. Very cute.
 //                      - Albert Liu, Dec 06, 2021 Mon 19:11 EST
+//
+// This is one row of `Nodes`. It's only used when a whole node needs to be
+// created or moved at once; everything else goes through the columns.
 #[derive(Clone, Copy)]
-pub(crate) struct Node<Info>
+pub(crate) struct Node<Info, const B: usize>
 where
     Info: BTreeInfo,
 {
@@ -16,10 +24,10 @@ where
     pub(crate) info: Info,
     pub(crate) count: usize,
     pub(crate) parent: Option<Idx>,
-    pub(crate) kids: Kids,
+    pub(crate) kids: Kids<Info, B>,
 }
 
-impl<Info> core::fmt::Debug for Node<Info>
+impl<Info, const B: usize> core::fmt::Debug for Node<Info, B>
 where
     Info: BTreeInfo, // + core::fmt::Debug,
 {
@@ -34,149 +42,454 @@ where
     }
 }
 
-impl<Info> Node<Info>
+impl<Info, const B: usize> Node<Info, B>
 where
     Info: BTreeInfo,
 {
-    pub fn assert_not_leaf(&self) {
+    pub fn empty(is_leaf: bool) -> Self {
+        return Node {
+            is_leaf,
+            count: 0,
+            info: Default::default(),
+            parent: None,
+            kids: Kids::new(),
+        };
+    }
+}
+
+// Nodes, stored struct-of-arrays style. Searches go through the summaries
+// cached in `Kids`, so the other columns only get touched when the tree is
+// being modified, and don't take up cache space during lookups. Counts and
+// infos are always read together, so they share a column.
+//
+// The kids column owns the free list; the other columns just have garbage in
+// them for freed slots.
+pub(crate) struct Nodes<Info, const B: usize>
+where
+    Info: BTreeInfo,
+{
+    kids: Arena<Kids<Info, B>>,
+    is_leaf: ChunkVec<bool>,
+    summaries: ChunkVec<(usize, Info)>,
+    parents: ChunkVec<Option<Idx>>,
+}
+
+impl<Info, const B: usize> Nodes<Info, B>
+where
+    Info: BTreeInfo,
+{
+    // Every node other than the root has at least this many kids
+    pub const MIN_KIDS: usize = B / 2;
+
+    pub fn new() -> Self {
+        const {
+            assert!(B >= 4, "B has to be at least 4 for nodes to stay balanced");
+        }
+
+        return Self {
+            kids: Arena::new(),
+            is_leaf: ChunkVec::new(),
+            summaries: ChunkVec::new(),
+            parents: ChunkVec::new(),
+        };
+    }
+
+    pub fn clear(&mut self) {
+        self.kids.clear();
+        self.is_leaf.clear();
+        self.summaries.clear();
+        self.parents.clear();
+    }
+
+    pub fn alloc(&mut self, node: Node<Info, B>) -> Idx {
+        let idx = self.kids.alloc(node.kids);
+        if idx.get() == self.is_leaf.len() {
+            self.is_leaf.push(node.is_leaf);
+            self.summaries.push((node.count, node.info));
+            self.parents.push(node.parent);
+            return idx;
+        }
+
+        self.is_leaf[idx.get()] = node.is_leaf;
+        self.summaries[idx.get()] = (node.count, node.info);
+        self.parents[idx.get()] = node.parent;
+
+        return idx;
+    }
+
+    pub fn free(&mut self, idx: Idx) -> Node<Info, B> {
+        let kids = self.kids.free(idx);
+        let (count, info) = self.summaries[idx.get()];
+        return Node {
+            is_leaf: self.is_leaf[idx.get()],
+            info,
+            count,
+            parent: self.parents[idx.get()],
+            kids,
+        };
+    }
+
+    // A copy of the whole node, or None if it's been freed
+    pub fn row(&self, idx: Idx) -> Option<Node<Info, B>> {
+        let kids = *self.kids.get(idx.get())?;
+        let (count, info) = self.summaries[idx.get()];
+        return Some(Node {
+            is_leaf: self.is_leaf[idx.get()],
+            info,
+            count,
+            parent: self.parents[idx.get()],
+            kids,
+        });
+    }
+
+    #[inline]
+    pub fn is_leaf(&self, idx: Idx) -> bool {
+        return self.is_leaf[idx.get()];
+    }
+
+    #[inline]
+    pub fn count(&self, idx: Idx) -> usize {
+        return self.summaries[idx.get()].0;
+    }
+
+    #[inline]
+    pub fn info(&self, idx: Idx) -> Info {
+        return self.summaries[idx.get()].1;
+    }
+
+    #[inline]
+    pub fn summary(&self, idx: Idx) -> (usize, Info) {
+        return self.summaries[idx.get()];
+    }
+
+    #[inline]
+    pub fn parent(&self, idx: Idx) -> Option<Idx> {
+        return self.parents[idx.get()];
+    }
+
+    #[inline]
+    pub fn kids(&self, idx: Idx) -> &Kids<Info, B> {
+        return &self.kids[idx.get()];
+    }
+
+    #[inline]
+    pub fn kids_mut(&mut self, idx: Idx) -> &mut Kids<Info, B> {
+        return &mut self.kids[idx.get()];
+    }
+
+    #[inline]
+    pub fn set_parent(&mut self, idx: Idx, parent: Option<Idx>) {
+        self.parents[idx.get()] = parent;
+    }
+
+    // Also updates the copy that the parent caches, if `idx` is already in
+    // its parent's kids. Otherwise whatever adds it to its parent has to
+    // provide the summary.
+    pub fn set_summary(&mut self, idx: Idx, count: usize, info: Info) {
+        self.summaries[idx.get()] = (count, info);
+
+        if let Some(parent) = self.parents[idx.get()] {
+            let kids = &mut self.kids[parent.get()];
+            if let Some(position) = kids.iter().position(|kid| kid == idx) {
+                kids.set_summary(position, (count, info));
+            }
+        }
+    }
+
+    // What each live node costs, across all of the columns
+    pub const ROW_BYTES: usize = core::mem::size_of::<Slot<Kids<Info, B>>>()
+        + core::mem::size_of::<bool>()
+        + core::mem::size_of::<(usize, Info)>()
+        + core::mem::size_of::<Option<Idx>>();
+
+    pub fn allocated_bytes(&self) -> usize {
+        return self.kids.allocated_bytes()
+            + self.is_leaf.allocated_bytes()
+            + self.summaries.allocated_bytes()
+            + self.parents.allocated_bytes();
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.kids.shrink_to_fit();
+        self.is_leaf.shrink_to_fit();
+        self.summaries.shrink_to_fit();
+        self.parents.shrink_to_fit();
+    }
+
+    pub fn assert_not_leaf(&self, idx: Idx) {
         #[cfg(debug_assertions)]
-        if self.is_leaf {
+        if self.is_leaf(idx) {
             core::panic!("thought it wouldnt be a leaf but it was")
         }
     }
 
-    pub fn assert_is_leaf(&self) {
+    pub fn assert_is_leaf(&self, idx: Idx) {
         #[cfg(debug_assertions)]
-        if !self.is_leaf {
+        if !self.is_leaf(idx) {
             core::panic!("thought it would be a leaf but it wasnt")
         }
     }
 
-    pub fn empty(is_leaf: bool) -> Self {
-        return Node {
-            is_leaf,
-            count: 0,
-            info: Default::default(),
-            parent: None,
-            kids: Kids::new(),
+    #[cfg(test)]
+    pub fn shared_chunks(&self) -> usize {
+        return self.kids.shared_chunks()
+            + self.is_leaf.shared_chunks()
+            + self.summaries.shared_chunks()
+            + self.parents.shared_chunks();
+    }
+
+    pub fn share(&self) -> Self {
+        return Self {
+            kids: self.kids.share(),
+            is_leaf: self.is_leaf.share(),
+            summaries: self.summaries.share(),
+            parents: self.parents.share(),
         };
     }
 }
 
+// The length is stored explicitly, and everything past it is filler. Each
+// kid's count and info are cached alongside it, which is what searches
+// actually read: that way a search only has to look at one node per level,
+// and never at the kids it skips over or at the elements in a leaf. For an
+// element the count is always 1.
+//
+// Counts and infos are separate columns, so that searching by index, which
+// is most searches, only reads the counts.
 #[derive(Clone, Copy)]
-pub struct Kids {
-    // First none is end of array
-    value: [Option<Idx>; B],
+pub struct Kids<Info, const B: usize>
+where
+    Info: BTreeInfo,
+{
+    len: usize,
+    value: [Idx; B],
+    counts: [usize; B],
+    infos: [Info; B],
 }
 
-impl From<[Idx; 2]> for Kids {
+impl<Info, const B: usize> From<[Idx; 2]> for Kids<Info, B>
+where
+    Info: BTreeInfo,
+{
     fn from(value: [Idx; 2]) -> Self {
+        return Self::from(&value[..]);
+    }
+}
+
+// The summaries are left empty, for the caller to fill in
+impl<Info, const B: usize> From<&[Idx]> for Kids<Info, B>
+where
+    Info: BTreeInfo,
+{
+    fn from(value: &[Idx]) -> Self {
         let mut sel = Self::new();
-        sel.value[0] = Some(value[0]);
-        sel.value[1] = Some(value[1]);
+        sel.value[..value.len()].copy_from_slice(value);
+        sel.len = value.len();
 
         return sel;
     }
 }
 
-impl Kids {
+impl<Info, const B: usize> Kids<Info, B>
+where
+    Info: BTreeInfo,
+{
     const SPLIT_POINT: usize = B / 2 + 1;
 
     pub fn new() -> Self {
-        return Self { value: [None; B] };
+        return Self {
+            len: 0,
+            value: [Idx::new(0); B],
+            counts: [0; B],
+            infos: [Info::default(); B],
+        };
     }
 
     pub fn remove_value(&mut self, value: Idx) -> usize {
-        let mut write_index = 0;
-        for index in 0..B {
-            let prev = match self.value[index].take() {
-                Some(prev) => prev,
-                None => return write_index,
-            };
-
-            if prev != value {
-                self.value[write_index] = Some(prev);
-                write_index += 1;
-            }
+        if let Some(index) = self.iter().position(|kid| kid == value) {
+            self.remove(index);
         }
 
-        return write_index;
+        return self.len;
     }
 
-    pub fn insert(&mut self, mut index: usize, mut value: Idx) -> Option<Self> {
-        if index > B || (index > 0 && self.value[index - 1].is_none()) {
+    pub fn insert(&mut self, index: usize, value: Idx, summary: (usize, Info)) -> Option<Self> {
+        if index > self.len {
             core::panic!("index out of bounds");
         }
 
-        while index < B {
-            value = self.value[index].replace(value)?;
-            index += 1;
+        if self.len < B {
+            self.shift_in(index, value, summary);
+            return None;
         }
 
-        let mut other = Self::new();
-        let mut index = 0;
-        for item in self.value[Self::SPLIT_POINT..].iter().map(|c| c.unwrap()) {
-            other.value[index] = Some(item);
-            index += 1;
+        // Split as if the new value was already in here, so that this ends up
+        // with SPLIT_POINT kids and the new node gets the rest
+        let mut other;
+        if index < Self::SPLIT_POINT {
+            other = self.split_off(Self::SPLIT_POINT - 1);
+            self.shift_in(index, value, summary);
+        } else {
+            other = self.split_off(Self::SPLIT_POINT);
+            other.shift_in(index - Self::SPLIT_POINT, value, summary);
         }
 
-        other.value[index] = Some(value);
+        return Some(other);
+    }
 
-        for slot in &mut self.value[Self::SPLIT_POINT..] {
-            *slot = None;
+    fn shift_in(&mut self, index: usize, value: Idx, (count, info): (usize, Info)) {
+        self.value.copy_within(index..self.len, index + 1);
+        self.counts.copy_within(index..self.len, index + 1);
+        self.infos.copy_within(index..self.len, index + 1);
+        self.value[index] = value;
+        self.counts[index] = count;
+        self.infos[index] = info;
+        self.len += 1;
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn push(&mut self, value: Idx, (count, info): (usize, Info)) {
+        if self.len == B {
+            core::panic!("pushed to full kids");
         }
 
-        return Some(other);
+        self.value[self.len] = value;
+        self.counts[self.len] = count;
+        self.infos[self.len] = info;
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<(Idx, (usize, Info))> {
+        if self.len == 0 {
+            return None;
+        }
+
+        self.len -= 1;
+        let summary = (self.counts[self.len], self.infos[self.len]);
+        return Some((self.value[self.len], summary));
     }
 
-    pub fn iter<'a>(&'a self) -> impl Iterator<Item = Idx> + 'a {
-        return self.into_iter();
+    pub fn remove(&mut self, index: usize) -> (Idx, (usize, Info)) {
+        if index >= self.len {
+            core::panic!("index out of bounds");
+        }
+
+        let removed = (self.value[index], self.summary(index));
+        self.value.copy_within((index + 1)..self.len, index);
+        self.counts.copy_within((index + 1)..self.len, index);
+        self.infos.copy_within((index + 1)..self.len, index);
+        self.len -= 1;
+
+        return removed;
+    }
+
+    // Leaves `self` with the first `at` kids and returns the rest
+    pub fn split_off(&mut self, at: usize) -> Self {
+        let mut other = Self::from(&self.value[at..self.len]);
+        other.counts[..other.len].copy_from_slice(&self.counts[at..self.len]);
+        other.infos[..other.len].copy_from_slice(&self.infos[at..self.len]);
+        self.len = at;
+
+        return other;
+    }
+
+    #[inline]
+    pub fn as_slice(&self) -> &[Idx] {
+        return &self.value[..self.len];
+    }
+
+    #[inline]
+    pub fn counts(&self) -> &[usize] {
+        return &self.counts[..self.len];
+    }
+
+    #[inline]
+    pub fn infos(&self) -> &[Info] {
+        return &self.infos[..self.len];
+    }
+
+    #[inline]
+    pub fn summary(&self, index: usize) -> (usize, Info) {
+        return (self.counts()[index], self.infos()[index]);
+    }
+
+    // Each kid's count and info, in order
+    #[inline]
+    pub fn summaries(&self) -> impl Iterator<Item = (usize, Info)> + '_ {
+        return self
+            .counts()
+            .iter()
+            .copied()
+            .zip(self.infos().iter().copied());
     }
 
-    pub fn iter_mut<'a>(&'a mut self) -> impl Iterator<Item = &mut Idx> + 'a {
-        return self.into_iter();
+    #[inline]
+    pub fn set_summary(&mut self, index: usize, (count, info): (usize, Info)) {
+        self.counts[..self.len][index] = count;
+        self.infos[..self.len][index] = info;
     }
 
-    pub fn rev(self) -> impl Iterator<Item = Idx> {
-        return self.value.into_iter().rev().filter_map(|i| i);
+    pub fn iter(&self) -> core::iter::Copied<core::slice::Iter<'_, Idx>> {
+        return self.as_slice().iter().copied();
+    }
+
+    pub fn iter_mut(&mut self) -> core::slice::IterMut<'_, Idx> {
+        return self.value[..self.len].iter_mut();
     }
 }
 
-impl core::ops::Index<usize> for Kids {
+impl<Info, const B: usize> core::ops::Index<usize> for Kids<Info, B>
+where
+    Info: BTreeInfo,
+{
     type Output = Idx;
 
     fn index(&self, index: usize) -> &Self::Output {
-        return self.value[index].as_ref().unwrap();
+        return &self.as_slice()[index];
     }
 }
 
-impl core::ops::IndexMut<usize> for Kids {
+impl<Info, const B: usize> core::ops::IndexMut<usize> for Kids<Info, B>
+where
+    Info: BTreeInfo,
+{
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        return self.value[index].as_mut().unwrap();
+        return &mut self.value[..self.len][index];
     }
 }
 
-type DerefIdx = fn(&Option<Idx>) -> Option<Idx>;
-impl<'a> core::iter::IntoIterator for &'a Kids {
+impl<'a, Info, const B: usize> core::iter::IntoIterator for &'a Kids<Info, B>
+where
+    Info: BTreeInfo,
+{
     type Item = Idx;
-    type IntoIter = core::iter::FilterMap<core::slice::Iter<'a, Option<Idx>>, DerefIdx>;
+    type IntoIter = core::iter::Copied<core::slice::Iter<'a, Idx>>;
 
     fn into_iter(self) -> Self::IntoIter {
-        return self.value.iter().filter_map(|i| *i);
+        return self.iter();
     }
 }
 
-type DerefMutIdx = fn(&mut Option<Idx>) -> Option<&mut Idx>;
-impl<'a> core::iter::IntoIterator for &'a mut Kids {
+impl<'a, Info, const B: usize> core::iter::IntoIterator for &'a mut Kids<Info, B>
+where
+    Info: BTreeInfo,
+{
     type Item = &'a mut Idx;
-    type IntoIter = core::iter::FilterMap<core::slice::IterMut<'a, Option<Idx>>, DerefMutIdx>;
+    type IntoIter = core::slice::IterMut<'a, Idx>;
 
     fn into_iter(self) -> Self::IntoIter {
-        return self.value.iter_mut().filter_map(|i| i.as_mut());
+        return self.iter_mut();
     }
 }
 
-impl core::fmt::Debug for Kids {
+impl<Info, const B: usize> core::fmt::Debug for Kids<Info, B>
+where
+    Info: BTreeInfo,
+{
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         return f.debug_list().entries(self).finish();
     }
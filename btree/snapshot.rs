@@ -0,0 +1,92 @@
+use crate::nodes::*;
+use crate::traits::*;
+use crate::tree::*;
+
+// Cloning a tree is cheap; both copies share all of their storage until one of
+// them writes to it, and then only the chunks that actually get written to are
+// copied. An edit usually only touches the chunks along the path from its
+// element up to the root. The clone gets its own handles, since both trees can
+// go on to add elements, so ElemIdx's from one don't work with the other.
+impl<T, const B: usize> Clone for BTree<T, B>
+where
+    T: BTreeItem,
+{
+    fn clone(&self) -> Self {
+        return Self {
+            handles: self.handles.clone(),
+            ..self.share()
+        };
+    }
+}
+
+impl<T, const B: usize> BTree<T, B>
+where
+    T: BTreeItem,
+{
+    /// A read-only copy of the tree as it is right now. Later edits to this
+    /// tree don't show up in the snapshot. ElemIdx's from this tree also work
+    /// with the snapshot, as long as the element existed when it was taken.
+    pub fn snapshot(&self) -> Snapshot<T, B> {
+        return Snapshot { tree: self.share() };
+    }
+
+    // A copy that shares everything with this tree, ElemIdx's included. Only
+    // snapshots can use this, since they never add elements of their own.
+    fn share(&self) -> Self {
+        return Self {
+            elements: self.elements.share(),
+            element_parents: self.element_parents.share(),
+            element_handles: self.element_handles.share(),
+            handles: self.handles.share(),
+            nodes: self.nodes.share(),
+            root: self.root,
+            levels: self.levels,
+        };
+    }
+}
+
+pub struct Snapshot<T, const B: usize = DEFAULT_B>
+where
+    T: BTreeItem,
+{
+    tree: BTree<T, B>,
+}
+
+impl<T, const B: usize> Clone for Snapshot<T, B>
+where
+    T: BTreeItem,
+{
+    fn clone(&self) -> Self {
+        return Self {
+            tree: self.tree.share(),
+        };
+    }
+}
+
+impl<T, const B: usize> core::ops::Deref for Snapshot<T, B>
+where
+    T: BTreeItem,
+{
+    type Target = BTree<T, B>;
+
+    fn deref(&self) -> &BTree<T, B> {
+        return &self.tree;
+    }
+}
+
+// Snapshots are meant to be handed off to other threads, like a highlighter
+// that works from the last version of the document while the user keeps
+// typing, so trees and snapshots both have to stay Send and Sync.
+#[allow(dead_code)]
+const _: () = {
+    fn assert_send_sync<S: Send + Sync>() {}
+
+    fn assert_tree<T>()
+    where
+        T: BTreeItem + Send + Sync,
+        T::Info: Send + Sync,
+    {
+        assert_send_sync::<BTree<T>>();
+        assert_send_sync::<Snapshot<T>>();
+    }
+};
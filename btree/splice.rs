@@ -0,0 +1,357 @@
+use crate::arena::*;
+use crate::chunks::*;
+use crate::handles::*;
+use crate::nodes::*;
+use crate::traits::*;
+use crate::tree::*;
+use crate::util::*;
+use alloc::vec::Vec;
+
+// Trees can't share arenas, so whichever side is smaller gets moved into the
+// other side's arena, one element and one node at a time. Cutting and joining
+// the nodes themselves is O(log n), so appending and splitting cost O(log n)
+// plus the size of the smaller side: appending a small tree to a big one, in
+// either order, or splitting a little bit off of either end is cheap no matter
+// how big the rest of the tree is, but splitting a tree down the middle copies
+// half of it. ElemIdx's belong to an arena, so they follow the bigger side to
+// whichever tree it ends up in; the elements that get moved get new handles.
+impl<T, const B: usize> BTree<T, B>
+where
+    T: BTreeItem,
+{
+    /// Moves everything in `other` to the end of this tree, leaving `other`
+    /// empty. The smaller of the two trees gets copied over element by
+    /// element, so this is O(log n) plus the length of the smaller tree.
+    /// ElemIdx's for the smaller tree's elements stop resolving.
+    pub fn append(&mut self, other: &mut Self) {
+        let mut other = core::mem::take(other);
+        if other.len() <= self.len() || self.len() == 0 {
+            self.append_moving(other);
+            return;
+        }
+
+        let (left, left_levels) = (self.root, self.levels);
+        let (right, right_levels) = (other.root, other.levels);
+        let left = other.adopt(self, left);
+        other.join((left, left_levels), (right, right_levels));
+        *self = other;
+    }
+
+    // Like `append`, but `other` is always the side that gets moved, so every
+    // ElemIdx for this tree stays good. That's what `extend` wants, since the
+    // elements it adds don't have handles that anyone could be holding yet.
+    pub(crate) fn append_moving(&mut self, mut other: Self) {
+        if other.len() == 0 {
+            return;
+        }
+
+        if self.len() == 0 {
+            *self = other;
+            return;
+        }
+
+        let (left, left_levels) = (self.root, self.levels);
+        let (right, right_levels) = (other.root, other.levels);
+        let right = self.adopt(&mut other, right);
+        self.join((left, left_levels), (right, right_levels));
+    }
+
+    /// Splits the tree in two at `at`. Afterwards this tree contains the
+    /// elements in `0..at` and the returned tree contains everything else.
+    /// The smaller half gets copied out element by element, so this is
+    /// O(log n) plus the length of the smaller half. ElemIdx's for the
+    /// smaller half's elements stop resolving.
+    pub fn split_off(&mut self, at: usize) -> Self {
+        let len = self.len();
+        if at > len {
+            core::panic!("split index was too high");
+        }
+
+        if at == 0 {
+            return core::mem::take(self);
+        }
+
+        if at == len {
+            return Self::default();
+        }
+
+        let (right, right_levels) = self.cut(at);
+
+        let mut other = Self::without_nodes();
+        if len - at <= at {
+            other.root = other.adopt(self, right);
+            other.levels = right_levels;
+
+            return other;
+        }
+
+        let left = self.root;
+        other.root = other.adopt(self, left);
+        other.levels = self.levels;
+        self.root = right;
+        self.levels = right_levels;
+
+        core::mem::swap(self, &mut other);
+        return other;
+    }
+
+    /// Removes the elements in `range` and returns them in order. Subtrees
+    /// that are entirely inside of the range get detached as a whole, so this
+    /// is O(log n) plus the number of elements removed.
+    pub fn drain(&mut self, range: impl core::ops::RangeBounds<usize>) -> Vec<T> {
+        let (begin, end) = resolve_range(range, self.len());
+        let mut drained = Vec::with_capacity(end - begin);
+        if begin == end {
+            return drained;
+        }
+
+        let tail = match end < self.len() {
+            true => Some(self.cut(end)),
+            false => None,
+        };
+
+        let middle = match begin > 0 {
+            true => self.cut(begin).0,
+            false => {
+                let middle = self.root;
+                self.root = self.nodes.alloc(Node::empty(true));
+                self.levels = 0;
+                middle
+            }
+        };
+
+        self.free_subtree(middle, &mut drained);
+
+        if let Some((tail, tail_levels)) = tail {
+            match begin > 0 {
+                true => self.join((self.root, self.levels), (tail, tail_levels)),
+                false => {
+                    self.nodes.free(self.root);
+                    self.root = tail;
+                    self.levels = tail_levels;
+                }
+            }
+        }
+
+        return drained;
+    }
+
+    // Cuts the tree in two at `at`, which has to be strictly inside of the
+    // tree. Afterwards this tree contains the elements in `0..at`, and the
+    // returned root and height are for a tree of everything else, which still
+    // lives in this tree's arenas.
+    fn cut(&mut self, at: usize) -> (Idx, usize) {
+        let (left, right) = self.split_nodes(at);
+        let levels = self.levels;
+
+        // Only the nodes along the cut can have ended up underfull
+        self.root = right;
+        self.rebalance_edge(false);
+        let right = (self.root, self.levels);
+
+        self.root = left;
+        self.levels = levels;
+        self.rebalance_edge(true);
+
+        return right;
+    }
+
+    // Fixes up the nodes along the first or last edge of the tree. A node
+    // whose parent only has one kid left can't be fixed until the parent has
+    // been merged with something, so one pass up the edge isn't always
+    // enough; this keeps going until nothing on the edge is underfull.
+    fn rebalance_edge(&mut self, last: bool) {
+        loop {
+            let leaf = match last {
+                true => self.last_pos(self.root).leaf,
+                false => self.first_pos(self.root).leaf,
+            };
+
+            self.root = self.rebalance_path(leaf);
+            self.collapse_root();
+
+            let mut node = self.root;
+            let mut clean = true;
+            for _ in 0..self.levels {
+                let kids = self.nodes.kids(node);
+                node = match last {
+                    true => kids[kids.len() - 1],
+                    false => kids[0],
+                };
+
+                if self.nodes.kids(node).len() < Nodes::<T::Info, B>::MIN_KIDS {
+                    clean = false;
+                    break;
+                }
+            }
+
+            if clean {
+                return;
+            }
+        }
+    }
+
+    // Frees `node` and everything under it, pushing its elements onto `out`
+    // in order
+    fn free_subtree(&mut self, node: Idx, out: &mut Vec<T>) {
+        let data = self.nodes.free(node);
+        for kid in &data.kids {
+            match data.is_leaf {
+                true => out.push(self.free_elem(kid)),
+                false => self.free_subtree(kid, out),
+            }
+        }
+    }
+
+    // Splits every node on the path down to the element at `at` into a left
+    // and a right half, and returns the roots of both halves. Both roots are at
+    // the same height as the original root.
+    fn split_nodes(&mut self, at: usize) -> (Idx, Idx) {
+        let mut path = Vec::with_capacity(self.levels);
+        let (mut node, mut index) = (self.root, at);
+        for _ in 0..self.levels {
+            let mut position = 0;
+            for &count in self.nodes.kids(node).counts() {
+                if index < count {
+                    break;
+                }
+
+                index -= count;
+                position += 1;
+            }
+
+            path.push((node, position));
+            node = self.nodes.kids(node)[position];
+        }
+
+        let kids = self.nodes.kids_mut(node).split_off(index);
+        let mut right = self.new_node(true, kids);
+        self.update_node(node);
+
+        let mut left = node;
+        for (parent, position) in path.into_iter().rev() {
+            let mut kids = self.nodes.kids_mut(parent).split_off(position + 1);
+            let split = kids.insert(0, right, self.nodes.summary(right));
+            debug_assert!(split.is_none());
+
+            if self.nodes.kids(left).len() == 0 {
+                self.remove_node(left);
+            }
+
+            right = self.new_node(false, kids);
+            self.update_node(parent);
+            left = parent;
+        }
+
+        return (left, right);
+    }
+
+    // Joins two trees that live in this tree's arenas, with everything in
+    // `left` ending up before everything in `right`. Afterwards the result is
+    // the root of this tree.
+    fn join(&mut self, (left, left_levels): (Idx, usize), (right, right_levels): (Idx, usize)) {
+        if left_levels == right_levels {
+            self.levels = left_levels;
+            if self.merge_or_balance(left, right) {
+                self.nodes.free(right);
+                self.root = left;
+                return;
+            }
+
+            self.root = self.new_node(false, [left, right]);
+            self.levels += 1;
+            return;
+        }
+
+        if left_levels > right_levels {
+            self.root = left;
+            self.levels = left_levels;
+
+            let mut parent = left;
+            for _ in (right_levels + 1)..left_levels {
+                let kids = self.nodes.kids(parent);
+                parent = kids[kids.len() - 1];
+            }
+
+            let kids = self.nodes.kids(parent);
+            let (index, sibling) = (kids.len(), kids[kids.len() - 1]);
+            let merged = self.merge_or_balance(sibling, right);
+            self.update_path(parent);
+
+            if merged {
+                self.nodes.free(right);
+                return;
+            }
+
+            let (count, info) = self.nodes.summary(right);
+            self.insert_kid(parent, index, right, count, info);
+            return;
+        }
+
+        self.root = right;
+        self.levels = right_levels;
+
+        let mut parent = right;
+        for _ in (left_levels + 1)..right_levels {
+            parent = self.nodes.kids(parent)[0];
+        }
+
+        let sibling = self.nodes.kids(parent)[0];
+        if self.merge_or_balance(left, sibling) {
+            // Everything is in `left` now, so it takes the sibling's place
+            let summary = self.nodes.summary(left);
+            self.nodes.set_parent(left, Some(parent));
+            let kids = self.nodes.kids_mut(parent);
+            kids[0] = left;
+            kids.set_summary(0, summary);
+            self.nodes.free(sibling);
+            self.update_path(parent);
+            return;
+        }
+
+        self.update_path(parent);
+
+        let (count, info) = self.nodes.summary(left);
+        self.insert_kid(parent, 0, left, count, info);
+    }
+
+    // Moves the subtree rooted at `node` out of `source` and into this tree's
+    // arenas, and returns its new index. The moved root has no parent.
+    pub(crate) fn adopt(&mut self, source: &mut Self, node: Idx) -> Idx {
+        let mut data = source.nodes.free(node);
+        let new_idx = self.nodes.alloc(Node::empty(data.is_leaf));
+
+        for kid in &mut data.kids {
+            *kid = match data.is_leaf {
+                true => {
+                    let elem = source.free_elem(*kid);
+                    self.allocate_elem(new_idx, elem)
+                }
+                false => {
+                    let new_kid = self.adopt(source, *kid);
+                    self.nodes.set_parent(new_kid, Some(new_idx));
+                    new_kid
+                }
+            };
+        }
+
+        *self.nodes.kids_mut(new_idx) = data.kids;
+        self.nodes.set_summary(new_idx, data.count, data.info);
+
+        return new_idx;
+    }
+
+    // A tree with nothing in it at all, not even a root. Callers need to set
+    // the root before doing anything else with it.
+    pub(crate) fn without_nodes() -> Self {
+        return Self {
+            elements: Arena::new(),
+            element_parents: ChunkVec::new(),
+            element_handles: ChunkVec::new(),
+            handles: Handles::new(),
+            nodes: Nodes::new(),
+            root: Idx::new(0),
+            levels: 0,
+        };
+    }
+}
@@ -1,3 +1,4 @@
+use crate::metric::*;
 use crate::tree::*;
 use crate::util::*;
 
@@ -8,9 +9,11 @@ where
     fn add(self, other: Self) -> Self;
 }
 
+// Items have to be Clone because trees share their storage with their
+// snapshots, and writing to shared storage copies it first.
 pub trait BTreeItem
 where
-    Self: Sized,
+    Self: Sized + Clone,
 {
     type Info: BTreeInfo;
 
@@ -21,18 +24,27 @@ pub trait BTreeIdx<T>
 where
     T: BTreeItem,
 {
-    fn get(self, tree: &BTree<T>) -> Option<Idx>;
+    fn get<const B: usize>(self, tree: &BTree<T, B>) -> Option<Idx>;
 }
 
-#[derive(Clone, Copy)]
-pub struct ElemIdx(pub(crate) Idx);
+// A handle to an element that stays valid while other elements are inserted
+// and removed, or while the tree shuffles its storage around. Once the element
+// itself is removed, the handle stops resolving to anything. Handles belong to
+// the tree's storage, so they never resolve in some other tree, not even a
+// clone of the tree they came from. Snapshots of that tree are the exception.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ElemIdx {
+    pub(crate) handle: Idx,
+    pub(crate) generation: u32,
+    pub(crate) owner: u32,
+}
 
 impl<T> BTreeIdx<T> for usize
 where
     T: BTreeItem,
 {
-    fn get(self, tree: &BTree<T>) -> Option<Idx> {
-        let (idx, _) = tree.find(false, self, |count, _| count)?;
+    fn get<const B: usize>(self, tree: &BTree<T, B>) -> Option<Idx> {
+        let (idx, _) = tree.find::<Count>(false, self)?;
         return Some(idx);
     }
 }
@@ -41,12 +53,21 @@ impl<T> BTreeIdx<T> for ElemIdx
 where
     T: BTreeItem,
 {
-    fn get(self, _tree: &BTree<T>) -> Option<Idx> {
-        return Some(self.0);
+    fn get<const B: usize>(self, tree: &BTree<T, B>) -> Option<Idx> {
+        return tree.handles.resolve(self);
+    }
+}
+
+impl<T> BTreeIdx<T> for Idx
+where
+    T: BTreeItem,
+{
+    fn get<const B: usize>(self, _tree: &BTree<T, B>) -> Option<Idx> {
+        return Some(self);
     }
 }
 
-impl<T, I> core::ops::Index<I> for BTree<T>
+impl<T, I, const B: usize> core::ops::Index<I> for BTree<T, B>
 where
     T: BTreeItem,
     I: BTreeIdx<T>,
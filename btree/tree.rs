@@ -1,97 +1,170 @@
+use crate::arena::*;
+use crate::chunks::*;
+use crate::handles::*;
+use crate::metric::*;
 use crate::nodes::*;
 use crate::traits::*;
 use crate::util::*;
-use alloc::vec;
-use alloc::vec::Vec;
 
-// Use SOA stuff eventually: https://github.com/lumol-org/soa-derive
-// They even have a trait! Very cute.
-pub struct BTree<T>
+// `B` is the most kids a node can have.
+pub struct BTree<T, const B: usize = DEFAULT_B>
 where
     T: BTreeItem,
 {
-    pub(crate) elements: Vec<T>,
-    pub(crate) element_parents: Vec<Idx>,
-    pub(crate) nodes: Vec<Node<T::Info>>,
+    pub(crate) elements: Arena<T>,
+    // Indexed by element slot; entries for freed slots are garbage.
+    pub(crate) element_parents: ChunkVec<Idx>,
+    pub(crate) element_handles: ChunkVec<Idx>,
+    pub(crate) handles: Handles,
+    pub(crate) nodes: Nodes<T::Info, B>,
     pub(crate) root: Idx,
     pub(crate) levels: usize,
 }
 
+// Like `HashMap::new`, this only exists for the default `B`, so that the type
+// of `BTree::new()` can be inferred. Other sizes use `BTree::default()`.
 impl<T> BTree<T>
 where
     T: BTreeItem,
 {
     pub fn new() -> Self {
-        let root_node = Node::empty(true);
+        return Self::default();
+    }
+}
+
+impl<T, const B: usize> Default for BTree<T, B>
+where
+    T: BTreeItem,
+{
+    fn default() -> Self {
+        let mut nodes = Nodes::new();
+        let root = nodes.alloc(Node::empty(true));
 
         return Self {
-            elements: Vec::new(),
-            element_parents: Vec::new(),
-            nodes: vec![root_node],
-            root: Idx::new(0),
+            elements: Arena::new(),
+            element_parents: ChunkVec::new(),
+            element_handles: ChunkVec::new(),
+            handles: Handles::new(),
+            nodes,
+            root,
             levels: 0,
         };
     }
+}
 
+impl<T, const B: usize> BTree<T, B>
+where
+    T: BTreeItem,
+{
     pub fn len(&self) -> usize {
-        return self.nodes[self.root.get()].count;
+        return self.nodes.count(self.root);
     }
 
     pub fn info(&self) -> T::Info {
-        return self.nodes[self.root.get()].info;
+        return self.nodes.info(self.root);
     }
 
-    pub fn sum_until<F>(&self, index: impl BTreeIdx<T>, get: F) -> Option<usize>
+    pub fn sum_until<M>(&self, index: impl BTreeIdx<T>) -> Option<usize>
     where
-        F: Fn(usize, T::Info) -> usize,
+        M: Metric<T>,
     {
         let elem_idx = index.get(self)?;
-        let mut sum = get(0, <T::Info as Default>::default());
+        let mut sum = 0;
 
         let mut node = self.element_parents[elem_idx.get()];
-        for kid in &self.nodes[node.get()].kids {
-            if kid == elem_idx {
+        sum += self.sum_before::<M>(node, elem_idx);
+
+        for _ in 0..self.levels {
+            let parent = self.nodes.parent(node).unwrap();
+            sum += self.sum_before::<M>(parent, node);
+            node = parent;
+        }
+
+        return Some(sum);
+    }
+
+    // Measures the kids of `node` that come before `kid`
+    fn sum_before<M>(&self, node: Idx, kid: Idx) -> usize
+    where
+        M: Metric<T>,
+    {
+        let kids = self.nodes.kids(node);
+        let mut sum = 0;
+        for (other, (count, info)) in kids.iter().zip(kids.summaries()) {
+            if other == kid {
                 break;
             }
 
-            let kid_info = self.elements[kid.get()].get_info();
-            sum += get(0, kid_info);
+            sum += M::measure(count, info);
         }
 
-        for _ in 0..self.levels {
-            let parent = self.nodes[node.get()].parent.unwrap();
+        return sum;
+    }
 
-            for kid in &self.nodes[parent.get()].kids {
-                if kid == node {
-                    break;
-                }
-                let kid = self.nodes[kid.get()];
-                sum += get(kid.count, kid.info);
+    // Combines the info of every element in `range`. Nodes that are entirely
+    // inside of the range use their cached info, so only the two paths down to
+    // the ends of the range get looked at in detail.
+    pub fn info_range(&self, range: impl core::ops::RangeBounds<usize>) -> T::Info {
+        let (begin, end) = resolve_range(range, self.len());
+        if begin == end {
+            return Default::default();
+        }
+
+        return self.info_between(self.root, begin, end);
+    }
+
+    fn info_between(&self, node: Idx, begin: usize, end: usize) -> T::Info {
+        if begin == 0 && end == self.nodes.count(node) {
+            return self.nodes.info(node);
+        }
+
+        let is_leaf = self.nodes.is_leaf(node);
+        let kids = self.nodes.kids(node);
+        let mut info = T::Info::default();
+        let mut offset = 0;
+        for (kid, (count, kid_info)) in kids.iter().zip(kids.summaries()) {
+            if offset >= end {
+                break;
             }
 
-            node = parent;
+            let (kid_begin, kid_end) = (begin.max(offset), end.min(offset + count));
+            if kid_begin < kid_end {
+                let kid_info = match is_leaf {
+                    true => kid_info,
+                    false => self.info_between(kid, kid_begin - offset, kid_end - offset),
+                };
+
+                info = info.add(kid_info);
+            }
+
+            offset += count;
         }
 
-        return Some(sum);
+        return info;
     }
 
-    pub fn find<F>(&self, inclusive: bool, mut key: usize, get: F) -> Option<(Idx, usize)>
+    // With `inclusive`, a key that's on the boundary between two elements
+    // finds the one before the boundary instead of the one after it.
+    pub(crate) fn find<M>(&self, inclusive: bool, mut key: usize) -> Option<(Idx, usize)>
     where
-        F: Fn(usize, T::Info) -> usize,
+        M: Metric<T>,
     {
-        let mut node = &self.nodes[self.root.get()];
-        if key > get(node.count, node.info) {
+        let nodes = &self.nodes;
+        let mut node = self.root;
+        let (count, info) = nodes.summary(node);
+        let total = M::measure(count, info);
+        if key > total {
             return None;
         }
 
-        if !inclusive && key == get(node.count, node.info) {
+        if !inclusive && key == total {
             return None;
         }
 
         'outer: for _ in 0..self.levels {
-            for child_idx in &node.kids {
-                let child = &self.nodes[child_idx.get()];
-                let val = get(child.count, child.info);
+            let kids = nodes.kids(node);
+            for (child, (count, info)) in kids.iter().zip(kids.summaries()) {
+                let val = M::measure(count, info);
                 if key < val {
                     node = child;
                     continue 'outer;
@@ -109,9 +182,9 @@ where
             return None;
         }
 
-        for idx in &node.kids {
-            let info = self.elements[idx.get()].get_info();
-            let val = get(1, info);
+        let kids = nodes.kids(node);
+        for (idx, (count, info)) in kids.iter().zip(kids.summaries()) {
+            let val = M::measure(count, info);
             if key < val {
                 return Some((idx, key));
             }
@@ -130,107 +203,92 @@ where
     pub fn remove(&mut self, index: impl BTreeIdx<T>) -> Option<T> {
         let idx = index.get(self)?;
 
-        let elem = self.elements.swap_remove(idx.get());
-        let leaf = self.element_parents.swap_remove(idx.get());
-        debug_assert_eq!(self.elements.len(), self.element_parents.len());
+        let leaf = self.element_parents[idx.get()];
+        let elem = self.free_elem(idx);
 
-        let mut new_size = self.nodes[leaf.get()].kids.remove_value(idx);
+        self.nodes.kids_mut(leaf).remove_value(idx);
 
-        // Update reference to swapped element
-        let old_idx = Idx::new(self.elements.len());
-        if old_idx != idx {
-            let swapped_parent = self.element_parents[idx.get()];
-            let mut kids = self.nodes[swapped_parent.get()].kids.iter_mut();
-            let slot = kids.find(|i| **i == old_idx).unwrap();
-            *slot = idx;
-        }
+        self.root = self.rebalance_path(leaf);
+        self.collapse_root();
 
-        let mut node = leaf;
-        for _ in 0..self.levels {
+        return Some(elem);
+    }
+
+    // Walks from `node` up to the root, updating bookkeeping and fixing up any
+    // node that has fewer than `Nodes::MIN_KIDS` kids along the way. Returns
+    // the root.
+    pub(crate) fn rebalance_path(&mut self, mut node: Idx) -> Idx {
+        while let Some(parent) = self.nodes.parent(node) {
             self.update_node(node);
-            let parent = match new_size {
-                0 => self.remove_node(node).parent.unwrap(),
-                _ => self.nodes[node.get()].parent.unwrap(),
-            };
+            if self.nodes.kids(node).len() < Nodes::<T::Info, B>::MIN_KIDS {
+                self.rebalance(node);
+            }
 
-            new_size = self.nodes[parent.get()].kids.iter().count();
             node = parent;
         }
-        self.update_node(node);
-
-        debug_assert_eq!(self.nodes[node.get()].parent, None);
 
-        let mut new_size = self.nodes[node.get()].kids.iter().count();
-        while new_size == 1 && self.levels > 0 {
-            let node_data = self.remove_node(node);
-            assert!(node_data.parent.is_none());
+        self.update_node(node);
+        return node;
+    }
 
-            let new_root = node_data.kids[0];
-            self.nodes[new_root.get()].parent = None;
-            new_size = self.nodes[new_root.get()].kids.iter().count();
-            node = new_root;
-            self.levels -= 1;
+    // Takes kids from a sibling of `node`, or merges it with the sibling if
+    // there's not enough to go around. If they merge, the node on the right is
+    // removed, and that might be `node` itself.
+    pub(crate) fn rebalance(&mut self, node: Idx) {
+        let parent = self.nodes.parent(node).unwrap();
+        let kids = self.nodes.kids(parent);
+        if kids.len() < 2 {
+            // The parent is underfull too, so it'll get fixed up next
+            return;
         }
 
-        self.root = node;
-
-        // TODO do more cleanup of tree.
+        let position = kids.iter().position(|kid| kid == node).unwrap();
+        let (left, right) = match position {
+            0 => (node, kids[1]),
+            position => (kids[position - 1], node),
+        };
 
-        return Some(elem);
+        if self.merge_or_balance(left, right) {
+            self.remove_node(right);
+        }
     }
 
-    pub(crate) fn remove_node(&mut self, node: Idx) -> Node<T::Info> {
-        let parent = self.nodes[node.get()].parent;
+    // Replaces the root with its only kid until the root has more than one kid
+    // or is a leaf.
+    pub(crate) fn collapse_root(&mut self) {
+        let mut node = self.root;
+        while self.levels > 0 && self.nodes.kids(node).len() == 1 {
+            let node_data = self.remove_node(node);
+            debug_assert!(node_data.parent.is_none());
 
-        if let Some(parent) = parent {
-            self.nodes[parent.get()].kids.remove_value(node);
+            node = node_data.kids[0];
+            self.nodes.set_parent(node, None);
+            self.levels -= 1;
         }
 
-        // Fix up references to node that will be moved
-        let move_idx = Idx::new(self.nodes.len() - 1);
-
-        if move_idx != node {
-            // the swapped node might be the root
-            if let Some(swapped_parent) = self.nodes[move_idx.get()].parent {
-                let mut kids = self.nodes[swapped_parent.get()].kids.iter_mut();
-                let slot = kids.find(|i| **i == move_idx).unwrap();
-                *slot = node;
-            }
-
-            let move_node = &self.nodes[move_idx.get()];
-            let (kids, is_leaf) = (move_node.kids, move_node.is_leaf);
-
-            if is_leaf {
-                for kid in &kids {
-                    self.element_parents[kid.get()] = node;
-                }
-            } else {
-                for kid in &kids {
-                    self.nodes[kid.get()].parent = Some(node);
-                }
-            }
-        }
+        self.root = node;
+    }
 
-        let mut node_data = self.nodes.swap_remove(node.get());
-        if node_data.parent == Some(move_idx) {
-            node_data.parent = Some(node);
+    pub(crate) fn remove_node(&mut self, node: Idx) -> Node<T::Info, B> {
+        if let Some(parent) = self.nodes.parent(node) {
+            self.nodes.kids_mut(parent).remove_value(node);
         }
 
-        return node_data;
+        return self.nodes.free(node);
     }
 
     // There's probably some kind of way to make this cute and work for any BTreeIdx,
     // but I couldn't figure it out in the 10 or so minutes I wasted trying.
     //                                  - Albert Liu, Dec 18, 2021 Sat 23:49 EST
     pub fn insert(&mut self, index: usize, elem: T) -> ElemIdx {
-        if index > self.nodes[self.root.get()].count {
+        if index > self.nodes.count(self.root) {
             core::panic!("insert index was too high");
         }
 
         let (mut node, mut index) = (self.root, index);
         'to_leaves: for _ in 0..self.levels {
-            for child in &self.nodes[node.get()].kids {
-                let count = self.nodes[child.get()].count;
+            let kids = self.nodes.kids(node);
+            for (child, &count) in kids.iter().zip(kids.counts()) {
                 if index <= count {
                     node = child;
                     continue 'to_leaves;
@@ -245,52 +303,80 @@ where
         return self.insert_into_leaf(node, index, elem);
     }
 
-    pub fn insert_before(&mut self, index: ElemIdx, elem: T) -> ElemIdx {
-        let leaf = self.element_parents[index.0.get()];
-        let mut kids_iter = self.nodes[leaf.get()].kids.into_iter();
-        let index = kids_iter.position(|kid| kid == index.0).unwrap();
-
-        return self.insert_into_leaf(leaf, index, elem);
+    pub fn insert_before(&mut self, index: ElemIdx, elem: T) -> Option<ElemIdx> {
+        let idx = self.handles.resolve(index)?;
+        let leaf = self.element_parents[idx.get()];
+        let index = self
+            .nodes
+            .kids(leaf)
+            .iter()
+            .position(|kid| kid == idx)
+            .unwrap();
+
+        return Some(self.insert_into_leaf(leaf, index, elem));
     }
 
-    pub fn insert_after(&mut self, index: ElemIdx, elem: T) -> ElemIdx {
-        let leaf = self.element_parents[index.0.get()];
-        let mut kids_iter = self.nodes[leaf.get()].kids.into_iter();
-        let index = kids_iter.position(|kid| kid == index.0).unwrap();
-
-        return self.insert_into_leaf(leaf, index + 1, elem);
+    pub fn insert_after(&mut self, index: ElemIdx, elem: T) -> Option<ElemIdx> {
+        let idx = self.handles.resolve(index)?;
+        let leaf = self.element_parents[idx.get()];
+        let index = self
+            .nodes
+            .kids(leaf)
+            .iter()
+            .position(|kid| kid == idx)
+            .unwrap();
+
+        return Some(self.insert_into_leaf(leaf, index + 1, elem));
     }
 
-    pub(crate) fn insert_into_leaf(&mut self, mut node: Idx, index: usize, elem: T) -> ElemIdx {
+    pub(crate) fn insert_into_leaf(&mut self, node: Idx, index: usize, elem: T) -> ElemIdx {
         let info = elem.get_info();
         let elem = self.allocate_elem(node, elem);
 
-        let mut right = self.add_child(node, index, elem, info).map(|kids| {
+        self.insert_kid(node, index, elem, 1, info);
+
+        return self.elem_idx(elem);
+    }
+
+    // Adds `kid` to `node` at `index`, splitting nodes on the way up to the root
+    // as necessary. `kid` is an element if `node` is a leaf, and otherwise it's
+    // a node; `count` and `info` are what it adds to each of its ancestors.
+    pub(crate) fn insert_kid(
+        &mut self,
+        mut node: Idx,
+        index: usize,
+        kid: Idx,
+        count: usize,
+        info: T::Info,
+    ) {
+        let is_leaf = self.nodes.is_leaf(node);
+        if !is_leaf {
+            self.nodes.set_parent(kid, Some(node));
+        }
+
+        let mut right = self.add_child(node, index, kid, count, info).map(|kids| {
             self.update_node(node);
-            return self.new_node(true, kids);
+            return self.new_node(is_leaf, kids);
         });
 
-        for _ in 0..self.levels {
-            let parent = self.nodes[node.get()].parent.unwrap();
-            self.nodes[parent.get()].assert_not_leaf();
+        while let Some(parent) = self.nodes.parent(node) {
+            self.nodes.assert_not_leaf(parent);
 
             let to_insert = match right.take() {
                 Some(right) => right,
                 None => {
                     // parent references are correct so everythings a-ok
-                    let parent_ref = &mut self.nodes[parent.get()];
-                    parent_ref.count += 1;
-                    parent_ref.info = parent_ref.info.add(info);
+                    self.add_to_summary(parent, count, info);
 
                     node = parent;
                     continue;
                 }
             };
 
-            self.nodes[to_insert.get()].parent = Some(parent);
-            let mut kids_iter = self.nodes[parent.get()].kids.into_iter();
-            let node_index = kids_iter.position(|kid| kid == node).unwrap() + 1;
-            let kids = self.add_child(parent, node_index, to_insert, info);
+            self.nodes.set_parent(to_insert, Some(parent));
+            let kids = self.nodes.kids(parent);
+            let node_index = kids.iter().position(|kid| kid == node).unwrap() + 1;
+            let kids = self.add_child(parent, node_index, to_insert, count, info);
             right = kids.map(|kids| {
                 self.update_node(parent);
                 self.new_node(false, kids)
@@ -299,22 +385,43 @@ where
             node = parent;
         }
 
-        right.map(|right| {
+        if let Some(right) = right {
             self.root = self.new_node(false, [node, right]);
             self.levels += 1;
-        });
-
-        return ElemIdx(elem);
+        }
     }
 
     pub(crate) fn allocate_elem(&mut self, parent: Idx, elem: T) -> Idx {
-        self.nodes[parent.get()].assert_is_leaf();
+        self.nodes.assert_is_leaf(parent);
 
-        let idx = self.elements.len();
-        self.elements.push(elem);
-        self.element_parents.push(parent);
+        return self.store_elem(parent, elem);
+    }
 
-        return Idx::new(idx);
+    // Puts `elem` in a slot and gives it a handle, without touching any nodes
+    pub(crate) fn store_elem(&mut self, parent: Idx, elem: T) -> Idx {
+        let idx = self.elements.alloc(elem);
+        let handle = self.handles.alloc(idx);
+        match idx.get() == self.element_parents.len() {
+            true => {
+                self.element_parents.push(parent);
+                self.element_handles.push(handle);
+            }
+            false => {
+                self.element_parents[idx.get()] = parent;
+                self.element_handles[idx.get()] = handle;
+            }
+        }
+
+        return idx;
+    }
+
+    pub(crate) fn free_elem(&mut self, idx: Idx) -> T {
+        self.handles.free(self.element_handles[idx.get()]);
+        return self.elements.free(idx);
+    }
+
+    pub(crate) fn elem_idx(&self, idx: Idx) -> ElemIdx {
+        return self.handles.elem_idx(self.element_handles[idx.get()]);
     }
 
     pub(crate) fn add_child(
@@ -322,65 +429,139 @@ where
         node: Idx,
         at: usize,
         child: Idx,
+        count: usize,
         info: T::Info,
-    ) -> Option<Kids> {
-        let kids = self.nodes[node.get()].kids.insert(at, child);
+    ) -> Option<Kids<T::Info, B>> {
+        let summary = self.kid_summary(self.nodes.is_leaf(node), child);
+        let kids = self.nodes.kids_mut(node).insert(at, child, summary);
         if kids.is_none() {
-            let node_ref = &mut self.nodes[node.get()];
-            node_ref.info = node_ref.info.add(info);
-            node_ref.count += 1;
+            self.add_to_summary(node, count, info);
         }
 
         return kids;
     }
 
+    #[inline]
+    fn add_to_summary(&mut self, node: Idx, count: usize, info: T::Info) {
+        let count = self.nodes.count(node) + count;
+        let info = self.nodes.info(node).add(info);
+        self.nodes.set_summary(node, count, info);
+    }
+
+    // `left` and `right` have to be neighbors on the same level. If all of their
+    // kids fit in one node, they get moved into `left` and this returns true.
+    // Otherwise the kids get spread evenly between the two. Either way, the
+    // ancestors of the two nodes still need to be updated afterwards.
+    pub(crate) fn merge_or_balance(&mut self, left: Idx, right: Idx) -> bool {
+        let is_leaf = self.nodes.is_leaf(left);
+        let left_len = self.nodes.kids(left).len();
+        let right_len = self.nodes.kids(right).len();
+        let total = left_len + right_len;
+
+        let target = match total <= B {
+            true => total,
+            false => total / 2,
+        };
+
+        for _ in target..left_len {
+            let (kid, summary) = self.nodes.kids_mut(left).pop().unwrap();
+            let split = self.nodes.kids_mut(right).insert(0, kid, summary);
+            debug_assert!(split.is_none());
+            self.set_parent(is_leaf, kid, right);
+        }
+
+        for _ in left_len..target {
+            let (kid, summary) = self.nodes.kids_mut(right).remove(0);
+            self.nodes.kids_mut(left).push(kid, summary);
+            self.set_parent(is_leaf, kid, left);
+        }
+
+        self.update_node(left);
+        self.update_node(right);
+
+        return total <= B;
+    }
+
+    #[inline]
+    pub(crate) fn set_parent(&mut self, is_leaf: bool, kid: Idx, parent: Idx) {
+        match is_leaf {
+            true => self.element_parents[kid.get()] = parent,
+            false => self.nodes.set_parent(kid, Some(parent)),
+        }
+    }
+
+    // Recomputes the bookkeeping for `node` and everything above it
+    pub(crate) fn update_path(&mut self, mut node: Idx) {
+        self.update_node(node);
+        while let Some(parent) = self.nodes.parent(node) {
+            self.update_node(parent);
+            node = parent;
+        }
+    }
+
+    // Recomputes the cached summary of `node` from the slots it keeps for its
+    // kids. `Nodes::set_summary` keeps the slots for nodes up to date, but an
+    // element that gets edited in place needs `refresh_elem` first.
     pub(crate) fn update_node(&mut self, node: Idx) {
-        let node_ref = &self.nodes[node.get()];
-        let (kids, is_leaf) = (node_ref.kids, node_ref.is_leaf);
         let (mut count, mut info) = (0, T::Info::default());
-        for kid in &kids {
-            let (kid_info, kid_count) = if is_leaf {
-                debug_assert!(self.element_parents[kid.get()] == node);
-                (self.elements[kid.get()].get_info(), 1)
-            } else {
-                let kid = &self.nodes[kid.get()];
-                debug_assert!(kid.parent == Some(node));
-                (kid.info, kid.count)
-            };
-
+        for (kid_count, kid_info) in self.nodes.kids(node).summaries() {
             info = info.add(kid_info);
             count += kid_count;
         }
 
-        let node_ = &mut self.nodes[node.get()];
-        node_.info = info;
-        node_.count = count;
+        self.nodes.set_summary(node, count, info);
     }
 
-    pub(crate) fn new_node(&mut self, is_leaf: bool, kids: impl Into<Kids>) -> Idx {
-        let kids: Kids = kids.into();
-        let idx = Idx::new(self.nodes.len());
-        let (mut count, mut info) = (0, T::Info::default());
-        for kid in &kids {
-            let (kid_info, kid_count) = if is_leaf {
-                self.element_parents[kid.get()] = idx;
-                (self.elements[kid.get()].get_info(), 1)
-            } else {
-                let kid = &mut self.nodes[kid.get()];
-                kid.parent = Some(idx);
-                (kid.info, kid.count)
-            };
+    // Re-reads the info of an element into the slot that its leaf keeps for
+    // it, and returns the leaf
+    pub(crate) fn refresh_elem(&mut self, idx: Idx) -> Idx {
+        let leaf = self.element_parents[idx.get()];
+        let info = self.elements[idx.get()].get_info();
+        let kids = self.nodes.kids_mut(leaf);
+        let position = kids.iter().position(|kid| kid == idx).unwrap();
+        kids.set_summary(position, (1, info));
 
-            info = info.add(kid_info);
-            count += kid_count;
+        return leaf;
+    }
+
+    // Re-reads the summary of every kid of `node` into its slot
+    fn refresh_slots(&mut self, node: Idx) {
+        let is_leaf = self.nodes.is_leaf(node);
+        let mut kids = *self.nodes.kids(node);
+        for index in 0..kids.len() {
+            let kid = kids[index];
+            debug_assert!(match is_leaf {
+                true => self.element_parents[kid.get()] == node,
+                false => self.nodes.parent(kid) == Some(node),
+            });
+
+            kids.set_summary(index, self.kid_summary(is_leaf, kid));
         }
 
-        let mut right_node = Node::empty(is_leaf);
-        right_node.kids = kids;
-        right_node.info = info;
-        right_node.count = count;
+        *self.nodes.kids_mut(node) = kids;
+    }
+
+    #[inline]
+    fn kid_summary(&self, is_leaf: bool, kid: Idx) -> (usize, T::Info) {
+        return match is_leaf {
+            true => (1, self.elements[kid.get()].get_info()),
+            false => self.nodes.summary(kid),
+        };
+    }
+
+    pub(crate) fn new_node(&mut self, is_leaf: bool, kids: impl Into<Kids<T::Info, B>>) -> Idx {
+        let idx = self.nodes.alloc(Node {
+            kids: kids.into(),
+            ..Node::empty(is_leaf)
+        });
+
+        for index in 0..self.nodes.kids(idx).len() {
+            let kid = self.nodes.kids(idx)[index];
+            self.set_parent(is_leaf, kid, idx);
+        }
 
-        self.nodes.push(right_node);
+        self.refresh_slots(idx);
+        self.update_node(idx);
 
         return idx;
     }
@@ -1,4 +1,5 @@
 use core::num::NonZeroUsize;
+use core::ops::{Bound, RangeBounds};
 
 pub(crate) struct NoPrettyPrint<T: core::fmt::Debug>(pub(crate) T);
 
@@ -11,7 +12,7 @@ where
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(transparent)]
 pub struct Idx(NonZeroUsize);
 
@@ -39,3 +40,27 @@ impl Idx {
         return self.0.get() - 1;
     }
 }
+
+// Turns `range` into a begin and end, panicking if it doesn't fit in `0..len`
+pub(crate) fn resolve_range(range: impl RangeBounds<usize>, len: usize) -> (usize, usize) {
+    let begin = match range.start_bound() {
+        Bound::Included(&i) => i,
+        Bound::Excluded(&i) => i + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&i) => i + 1,
+        Bound::Excluded(&i) => i,
+        Bound::Unbounded => len,
+    };
+
+    if begin > end {
+        core::panic!("range begin was greater than range end");
+    }
+
+    if end > len {
+        core::panic!("range end was too high");
+    }
+
+    return (begin, end);
+}
@@ -1,4 +1,5 @@
 use crate::editor::fonts::*;
+use crate::editor::text::*;
 use crate::editor::types::*;
 use crate::editor::view::*;
 use crate::util::*;
@@ -25,7 +26,7 @@ pub struct CommandHandler<P: Platform> {
 impl<P: Platform> CommandHandler<P> {
     pub fn new(platform: P, text: String) -> Self {
         let cache = GlyphCache::new();
-        let view = View::new(new_rect(35, 20), &text);
+        let view = View::new(new_rect(35, 20), Document::from(text.as_str()));
 
         return Self {
             cache,
@@ -58,7 +59,17 @@ impl<P: Platform> CommandHandler<P> {
                 } => {
                     let text_len = text.len();
                     let mut glyphs = Pod::with_capacity(text_len);
-                    for c in text.into_iter() {
+                    let mut cells = text.into_iter().peekable();
+                    while let Some(c) = cells.next() {
+                        if cells.peek() == Some(&WIDE_CHAR_TAIL) {
+                            cells.next();
+
+                            let (left, right) = self.cache.translate_wide_glyph(c);
+                            glyphs.push(left);
+                            glyphs.push(right);
+                            continue;
+                        }
+
                         let glyph = self.cache.translate_glyph(c);
                         glyphs.push(glyph);
                     }
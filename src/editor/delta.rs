@@ -0,0 +1,292 @@
+use crate::util::*;
+
+// A change to a document, as a list of spans that walk over the old text from
+// the beginning. Lengths are in chars, and anything after the last span is
+// left alone. Deletes keep the text that they deleted, so that a delta can be
+// inverted without looking at the document it came from.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Delta {
+    pub ops: Vec<DeltaOp>,
+}
+
+// Which side of an insert a position ends up on, when the insert happens
+// exactly at that position
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Gravity {
+    Left,
+    Right,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum DeltaOp {
+    Retain(usize),
+    Insert(String),
+    Delete(String),
+}
+
+impl DeltaOp {
+    pub fn len(&self) -> usize {
+        return match self {
+            DeltaOp::Retain(len) => *len,
+            DeltaOp::Insert(text) | DeltaOp::Delete(text) => text.chars().count(),
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+}
+
+impl Delta {
+    pub fn new() -> Self {
+        return Self { ops: Vec::new() };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.ops.is_empty();
+    }
+
+    pub fn retain(&mut self, len: usize) {
+        self.push(DeltaOp::Retain(len));
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        self.push(DeltaOp::Insert(text.to_string()));
+    }
+
+    pub fn delete(&mut self, text: &str) {
+        self.push(DeltaOp::Delete(text.to_string()));
+    }
+
+    // Merges the op into the last one when they're the same kind, so that
+    // equal changes always end up with equal deltas
+    pub fn push(&mut self, op: DeltaOp) {
+        if op.is_empty() {
+            return;
+        }
+
+        match (self.ops.last_mut(), op) {
+            (Some(DeltaOp::Retain(last)), DeltaOp::Retain(len)) => *last += len,
+            (Some(DeltaOp::Insert(last)), DeltaOp::Insert(text)) => last.push_str(&text),
+            (Some(DeltaOp::Delete(last)), DeltaOp::Delete(text)) => last.push_str(&text),
+            (_, op) => self.ops.push(op),
+        }
+    }
+
+    // Trailing retains don't do anything
+    fn trim(&mut self) {
+        if let Some(DeltaOp::Retain(_)) = self.ops.last() {
+            self.ops.pop();
+        }
+    }
+
+    // The delta that undoes this one
+    pub fn invert(&self) -> Delta {
+        let ops = self.ops.iter().map(|op| match op {
+            DeltaOp::Retain(len) => DeltaOp::Retain(*len),
+            DeltaOp::Insert(text) => DeltaOp::Delete(text.clone()),
+            DeltaOp::Delete(text) => DeltaOp::Insert(text.clone()),
+        });
+
+        return Delta { ops: ops.collect() };
+    }
+
+    // Where `cursor` ends up after this delta. Positions inside of deleted
+    // text end up where the deleted text was.
+    pub fn transform(&self, cursor: usize, gravity: Gravity) -> usize {
+        let (mut old, mut new) = (0, 0);
+        for op in &self.ops {
+            let len = op.len();
+            match op {
+                DeltaOp::Retain(_) => {
+                    if cursor < old + len {
+                        return new + (cursor - old);
+                    }
+
+                    old += len;
+                    new += len;
+                }
+                DeltaOp::Insert(_) => {
+                    if cursor == old && gravity == Gravity::Left {
+                        return new;
+                    }
+
+                    new += len;
+                }
+                DeltaOp::Delete(_) => {
+                    if cursor < old + len {
+                        return new;
+                    }
+
+                    old += len;
+                }
+            }
+        }
+
+        return new + (cursor - old);
+    }
+
+    // One delta that does the same thing as applying `self` and then `other`
+    pub fn compose(&self, other: &Delta) -> Delta {
+        let mut result = Delta::new();
+        let mut first = OpIter::new(&self.ops);
+        let mut second = OpIter::new(&other.ops);
+
+        while !first.done() || !second.done() {
+            // Inserts from the second delta go in before anything that the
+            // first delta did at the same spot, and text that the first delta
+            // deleted isn't there for the second one to see
+            if let Some(DeltaOp::Insert(_)) = second.peek() {
+                result.push(second.next(usize::MAX));
+                continue;
+            }
+
+            if let Some(DeltaOp::Delete(_)) = first.peek() {
+                result.push(first.next(usize::MAX));
+                continue;
+            }
+
+            let len = first.peek_len().min(second.peek_len());
+            match (first.next(len), second.next(len)) {
+                (DeltaOp::Retain(_), DeltaOp::Retain(len)) => result.retain(len),
+                (DeltaOp::Retain(_), DeltaOp::Delete(text)) => result.delete(&text),
+                (DeltaOp::Insert(text), DeltaOp::Retain(_)) => result.insert(&text),
+                (DeltaOp::Insert(_), DeltaOp::Delete(_)) => {}
+                _ => unreachable!(),
+            }
+        }
+
+        result.trim();
+        return result;
+    }
+}
+
+// Walks over a list of ops, handing out pieces of them. Past the end, there's
+// an endless retain.
+struct OpIter<'a> {
+    ops: &'a [DeltaOp],
+    index: usize,
+    // Chars of the current op that have already been handed out
+    offset: usize,
+}
+
+impl<'a> OpIter<'a> {
+    fn new(ops: &'a [DeltaOp]) -> Self {
+        return Self {
+            ops,
+            index: 0,
+            offset: 0,
+        };
+    }
+
+    fn done(&self) -> bool {
+        return self.index >= self.ops.len();
+    }
+
+    fn peek(&self) -> Option<&'a DeltaOp> {
+        return self.ops.get(self.index);
+    }
+
+    fn peek_len(&self) -> usize {
+        return match self.peek() {
+            Some(op) => op.len() - self.offset,
+            None => usize::MAX,
+        };
+    }
+
+    fn next(&mut self, len: usize) -> DeltaOp {
+        let op = match self.peek() {
+            Some(op) => op,
+            None => return DeltaOp::Retain(len),
+        };
+
+        let begin = self.offset;
+        let len = len.min(op.len() - begin);
+        self.offset += len;
+        if self.offset == op.len() {
+            self.index += 1;
+            self.offset = 0;
+        }
+
+        let piece = |text: &str| text.chars().skip(begin).take(len).collect();
+        return match op {
+            DeltaOp::Retain(_) => DeltaOp::Retain(len),
+            DeltaOp::Insert(text) => DeltaOp::Insert(piece(text)),
+            DeltaOp::Delete(text) => DeltaOp::Delete(piece(text)),
+        };
+    }
+}
+
+#[test]
+fn test_delta_compose() {
+    fn apply(text: &str, delta: &Delta) -> String {
+        let mut chars = text.chars();
+        let mut out = String::new();
+        for op in &delta.ops {
+            match op {
+                DeltaOp::Retain(len) => out.extend((&mut chars).take(*len)),
+                DeltaOp::Insert(text) => out.push_str(text),
+                DeltaOp::Delete(text) => {
+                    let deleted: String = (&mut chars).take(text.chars().count()).collect();
+                    assert_eq!(&deleted, text);
+                }
+            }
+        }
+
+        out.extend(chars);
+        return out;
+    }
+
+    let text = "hello wörld";
+
+    let mut first = Delta::new();
+    first.retain(5);
+    first.insert(", there");
+    first.retain(1);
+    first.delete("wö");
+    first.insert("WO");
+
+    let mut second = Delta::new();
+    second.delete("hel");
+    second.retain(4);
+    second.delete("there W");
+    second.insert("!");
+
+    let middle = apply(text, &first);
+    assert_eq!(middle, "hello, there WOrld");
+    assert_eq!(apply(&middle, &second), "lo, !Orld");
+
+    let composed = first.compose(&second);
+    assert_eq!(apply(text, &composed), "lo, !Orld");
+    assert_eq!(apply(&apply(text, &composed), &composed.invert()), text);
+
+    let mut expected = Delta::new();
+    expected.delete("hel");
+    expected.retain(2);
+    expected.insert(", ");
+    expected.delete(" wö");
+    expected.insert("!O");
+    assert_eq!(composed, expected);
+
+    assert_eq!(Delta::new().compose(&second), second);
+    assert_eq!(first.compose(&Delta::new()), first);
+}
+
+#[test]
+fn test_delta_transform() {
+    let mut delta = Delta::new();
+    delta.retain(2);
+    delta.insert("abc");
+    delta.retain(1);
+    delta.delete("xyz");
+    delta.insert("!");
+
+    let left: Vec<usize> = (0..10).map(|i| delta.transform(i, Gravity::Left)).collect();
+    let right: Vec<usize> = (0..10)
+        .map(|i| delta.transform(i, Gravity::Right))
+        .collect();
+    assert_eq!(left, [0, 1, 2, 6, 6, 6, 6, 8, 9, 10]);
+    assert_eq!(right, [0, 1, 5, 6, 6, 6, 7, 8, 9, 10]);
+
+    assert_eq!(Delta::new().transform(7, Gravity::Left), 7);
+}
@@ -31,7 +31,9 @@ pub struct GlyphData {
 }
 
 pub struct GlyphCache {
-    descriptors: HashMap<char, Point2<u32>>,
+    // Wide chars get their own, wider glyphs, so the key has how many cells
+    // the glyph is for
+    descriptors: HashMap<(char, u32), Point2<u32>>,
     atlas: Pod<u8>,
     glyph_dims: Rect,
     atlas_dims: Rect,
@@ -69,9 +71,24 @@ impl GlyphCache {
     }
 
     pub fn translate_glyph(&mut self, c: char) -> Glyph {
-        if let Some(&pos) = self.descriptors.get(&c) {
-            let glyph = self.make_glyph(pos);
-            return glyph;
+        let pos = self.glyph_pos(c, 1);
+        return self.make_glyph(pos);
+    }
+
+    // A glyph that's 2 cells wide, split into a glyph for each of the cells
+    pub fn translate_wide_glyph(&mut self, c: char) -> (Glyph, Glyph) {
+        let mut pos = self.glyph_pos(c, 2);
+        let left = self.make_glyph(pos);
+
+        pos.x += self.glyph_dims.x;
+        let right = self.make_glyph(pos);
+
+        return (left, right);
+    }
+
+    fn glyph_pos(&mut self, c: char, cells: u32) -> Point2<u32> {
+        if let Some(&pos) = self.descriptors.get(&(c, cells)) {
+            return pos;
         }
 
         let face = expect(ttf::Face::from_slice(COURIER, 0));
@@ -98,9 +115,7 @@ impl GlyphCache {
         let descent = (descent as f32 * -scale) as i32;
         let descent = expect(descent.try_into());
         if width < self.glyph_dims.x && height < self.glyph_dims.y {
-            let pos = self.add_char(&face, scale, descent, c);
-            let glyph = self.make_glyph(pos);
-            return glyph;
+            return self.add_char(&face, scale, descent, c, cells);
         }
 
         self.descriptors.clear();
@@ -113,12 +128,10 @@ impl GlyphCache {
         self.atlas_current_row_width = self.atlas_dims.x;
 
         for c in DEFAULT_CHARS.chars() {
-            self.add_char(&face, scale, descent, c);
+            self.add_char(&face, scale, descent, c, 1);
         }
 
-        let pos = self.add_char(&face, scale, descent, c);
-        let glyph = self.make_glyph(pos);
-        return glyph;
+        return self.add_char(&face, scale, descent, c, cells);
     }
 
     fn make_glyph(&self, mut glyph: Point2<u32>) -> Glyph {
@@ -143,8 +156,15 @@ impl GlyphCache {
         };
     }
 
-    fn add_char(&mut self, face: &ttf::Face, scale: f32, descent: usize, c: char) -> Point2<u32> {
-        if let Some(&glyph) = self.descriptors.get(&c) {
+    fn add_char(
+        &mut self,
+        face: &ttf::Face,
+        scale: f32,
+        descent: usize,
+        c: char,
+        cells: u32,
+    ) -> Point2<u32> {
+        if let Some(&glyph) = self.descriptors.get(&(c, cells)) {
             if (self.atlas_dims.y * self.atlas_dims.x) != (self.atlas.len() as u32) {
                 panic!("atlas is in invalid state");
             }
@@ -152,7 +172,8 @@ impl GlyphCache {
             return glyph;
         }
 
-        if self.atlas_current_row_width + self.glyph_dims.x >= self.atlas_dims.x {
+        let width = self.glyph_dims.x * cells;
+        if self.atlas_current_row_width + width >= self.atlas_dims.x {
             let glyph_row_size = self.atlas_dims.x * self.glyph_dims.y;
 
             self.atlas.reserve(glyph_row_size as usize);
@@ -164,16 +185,18 @@ impl GlyphCache {
             self.atlas_dims.y += self.glyph_dims.y;
         }
 
-        let glyph_id = unwrap(face.glyph_index(c));
+        // Courier doesn't have most wide chars, so those end up as the
+        // font's "missing glyph" box
+        let glyph_id = face.glyph_index(c).unwrap_or(ttf::GlyphId(0));
         let glyph_data = rasterize_glyph(face, scale, glyph_id);
 
         let x = self.atlas_current_row_width;
-        self.atlas_current_row_width += self.glyph_dims.x;
+        self.atlas_current_row_width += width;
         let y = self.atlas_dims.y - self.glyph_dims.y;
 
         let glyph = Point2 { x, y };
         self.write_glyph_data(glyph, descent, glyph_data);
-        self.descriptors.insert(c, glyph);
+        self.descriptors.insert((c, cells), glyph);
 
         if (self.atlas_dims.y * self.atlas_dims.x) != (self.atlas.len() as u32) {
             panic!("atlas is in invalid state");
@@ -10,6 +10,7 @@ pub const KEYWORD: Color = color(0.522, 0.6, 0.0);
 
 pub const LINES_FG: Color = color(0.396, 0.482, 0.514);
 pub const LINES_BG: Color = color(0.027, 0.212, 0.259);
+pub const SELECTION_BG: Color = color(0.149, 0.329, 0.388);
 
 pub const DEFAULT_FG: Color = NORMAL;
 pub const DEFAULT_BG: Color = TEXT_BG;
@@ -45,6 +46,7 @@ pub struct Highlighter {
     regexes: Pod<RegexItem>,
     rules: Pod<Rule>,
     scopes: Pod<Scope>,
+    tab_width: Option<u32>,
 }
 
 impl Highlighter {
@@ -105,6 +107,7 @@ impl Highlighter {
         let mut variables = HashMap::new();
         let mut regexes = Pod::new();
         let mut scope_name = "default";
+        let mut tab_width = None;
 
         for (name, value) in values {
             // used to extend the lifetime of GonValue::String values
@@ -202,6 +205,11 @@ impl Highlighter {
                 continue;
             }
 
+            if name == "tab_width" {
+                tab_width = Some(expect(text.parse()));
+                continue;
+            }
+
             let color = expect_color(&variables, &GonValue::Str(text));
             if let Some(prev) = variables.insert(name, color) {
                 panic!("variable redefined");
@@ -248,9 +256,14 @@ impl Highlighter {
             regexes,
             rules,
             scopes: scope_values,
+            tab_width,
         };
     }
 
+    pub fn tab_width(&self) -> Option<u32> {
+        return self.tab_width;
+    }
+
     pub fn ranges(&self, text: &[char]) -> Pod<HLRange> {
         let mut ranges = Pod::new();
 
@@ -388,6 +401,8 @@ fn get_field<'a, 'b>(
 // Builtins:
 // default scope # the default scope
 //
+// tab_width 4 # optional; how many columns apart tab stops are
+//
 // default {
 //   # default rule for scope; for the default scope, it is required, and
 //   # all its fields are also required.
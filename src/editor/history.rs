@@ -0,0 +1,278 @@
+use crate::editor::delta::*;
+use crate::editor::selection::*;
+use crate::util::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EditKind {
+    Insert,
+    Delete,
+    Other,
+}
+
+// Undo history, as a tree of changes. Undoing something and then making a new
+// change starts a new branch, instead of throwing away what was undone; redo
+// follows whichever branch was made or visited most recently.
+pub struct History {
+    // The first step is the root, i.e. the document before any changes
+    steps: Vec<Step>,
+    current: usize,
+}
+
+struct Step {
+    parent: usize,
+    kids: Vec<usize>,
+    redo: Option<usize>,
+    kind: EditKind,
+
+    // Goes from the parent's text to this step's text
+    delta: Delta,
+    before: Vec<Selection>,
+    after: Vec<Selection>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        let root = Step {
+            parent: 0,
+            kids: Vec::new(),
+            redo: None,
+            kind: EditKind::Other,
+            delta: Delta::new(),
+            before: vec![Selection::cursor(0)],
+            after: vec![Selection::cursor(0)],
+        };
+
+        return Self {
+            steps: vec![root],
+            current: 0,
+        };
+    }
+
+    pub fn can_undo(&self) -> bool {
+        return self.current != 0;
+    }
+
+    pub fn can_redo(&self) -> bool {
+        return self.steps[self.current].redo.is_some();
+    }
+
+    pub fn record(
+        &mut self,
+        delta: Delta,
+        kind: EditKind,
+        before: Vec<Selection>,
+        after: Vec<Selection>,
+    ) {
+        if delta.is_empty() {
+            return;
+        }
+
+        if self.can_merge(&delta, kind, &before) {
+            let step = &mut self.steps[self.current];
+            step.delta = step.delta.compose(&delta);
+            step.after = after;
+            return;
+        }
+
+        let step = self.steps.len();
+        let current = &mut self.steps[self.current];
+        current.kids.push(step);
+        current.redo = Some(step);
+
+        self.steps.push(Step {
+            parent: self.current,
+            kids: Vec::new(),
+            redo: None,
+            kind,
+            delta,
+            before,
+            after,
+        });
+
+        self.current = step;
+    }
+
+    // Typing a word, or backspacing over a bunch of text, is one step. Typing
+    // breaks into a new step at the beginning of each word and at newlines;
+    // moving the cursor in between edits always breaks the step.
+    fn can_merge(&self, delta: &Delta, kind: EditKind, before: &[Selection]) -> bool {
+        let step = &self.steps[self.current];
+        if !self.can_undo() || step.kind != kind || step.after != before {
+            return false;
+        }
+
+        // Steps with kids can't change, or the kids' deltas would be wrong
+        if !step.kids.is_empty() {
+            return false;
+        }
+
+        // With more than one cursor, typing inserts in more than one place,
+        // so steps just keep going for as long as the cursors don't move
+        if before.len() > 1 {
+            return kind != EditKind::Other;
+        }
+
+        return match kind {
+            EditKind::Insert => {
+                let text = match inserted(delta) {
+                    Some(text) => text,
+                    None => return false,
+                };
+
+                let prev = inserted(&step.delta).and_then(|text| text.chars().last());
+                let next = text.chars().next();
+                match (prev, next) {
+                    (_, None) => true,
+                    (None, _) => false,
+                    (Some(prev), Some(next)) => {
+                        let word_begins = prev.is_whitespace() && !next.is_whitespace();
+                        !word_begins && prev != '\n' && !text.contains('\n')
+                    }
+                }
+            }
+            EditKind::Delete => true,
+            EditKind::Other => false,
+        };
+    }
+
+    // The delta that undoes the current step, and where the cursors were
+    // before the step happened
+    pub fn undo(&mut self) -> Option<(Delta, Vec<Selection>)> {
+        if !self.can_undo() {
+            return None;
+        }
+
+        let step = &self.steps[self.current];
+        let (parent, delta, before) = (step.parent, step.delta.invert(), step.before.clone());
+        self.steps[parent].redo = Some(self.current);
+        self.current = parent;
+
+        return Some((delta, before));
+    }
+
+    // Points redo at the next branch off of the current step, wrapping around
+    // after the last one. Returns how many branches there are.
+    pub fn next_branch(&mut self) -> usize {
+        let step = &mut self.steps[self.current];
+        if let Some(redo) = step.redo {
+            let index = unwrap(step.kids.iter().position(|&kid| kid == redo));
+            step.redo = Some(step.kids[(index + 1) % step.kids.len()]);
+        }
+
+        return step.kids.len();
+    }
+
+    pub fn redo(&mut self) -> Option<(Delta, Vec<Selection>)> {
+        let next = self.steps[self.current].redo?;
+        self.current = next;
+
+        let step = &self.steps[next];
+        return Some((step.delta.clone(), step.after.clone()));
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+// The text that a delta inserts, if that's all that it does
+fn inserted(delta: &Delta) -> Option<&str> {
+    let mut text = None;
+    for op in &delta.ops {
+        match op {
+            DeltaOp::Retain(_) => {}
+            DeltaOp::Insert(insert) if text.is_none() => text = Some(insert.as_str()),
+            _ => return None,
+        }
+    }
+
+    return text;
+}
+
+#[test]
+fn test_history() {
+    let mut history = History::new();
+    let mut text = String::new();
+    let mut type_text = |history: &mut History, text: &mut String, typed: &str| {
+        for c in typed.chars() {
+            let cursor = text.chars().count();
+            let mut delta = Delta::new();
+            delta.retain(cursor);
+            delta.insert(&c.to_string());
+            text.push(c);
+
+            let before = vec![Selection::cursor(cursor)];
+            let after = vec![Selection::cursor(cursor + 1)];
+            history.record(delta, EditKind::Insert, before, after);
+        }
+    };
+
+    fn apply(text: &mut String, (delta, selections): (Delta, Vec<Selection>)) -> usize {
+        let mut chars = text.chars();
+        let mut out = String::new();
+        for op in &delta.ops {
+            match op {
+                DeltaOp::Retain(len) => out.extend((&mut chars).take(*len)),
+                DeltaOp::Insert(text) => out.push_str(text),
+                DeltaOp::Delete(text) => {
+                    for _ in text.chars() {
+                        chars.next();
+                    }
+                }
+            }
+        }
+
+        out.extend(chars);
+        *text = out;
+        return selections[0].head;
+    }
+
+    type_text(&mut history, &mut text, "hello big\nworld");
+    assert_eq!(text, "hello big\nworld");
+
+    // "hello ", "big", "\n", "world"
+    assert_eq!(apply(&mut text, history.undo().unwrap()), 10);
+    assert_eq!(text, "hello big\n");
+    assert_eq!(apply(&mut text, history.undo().unwrap()), 9);
+    assert_eq!(apply(&mut text, history.undo().unwrap()), 6);
+    assert_eq!(text, "hello ");
+    assert_eq!(apply(&mut text, history.redo().unwrap()), 9);
+    assert_eq!(text, "hello big");
+
+    // Typing after an undo starts a branch, and the old one is still there
+    assert_eq!(apply(&mut text, history.undo().unwrap()), 6);
+    type_text(&mut history, &mut text, "small");
+    assert_eq!(text, "hello small");
+    assert!(!history.can_redo());
+
+    apply(&mut text, history.undo().unwrap());
+    assert_eq!(text, "hello ");
+    apply(&mut text, history.undo().unwrap());
+    assert_eq!(text, "");
+    assert!(history.undo().is_none());
+
+    apply(&mut text, history.redo().unwrap());
+    assert_eq!(history.next_branch(), 2);
+    apply(&mut text, history.redo().unwrap());
+    assert_eq!(text, "hello big");
+
+    apply(&mut text, history.undo().unwrap());
+    assert_eq!(history.next_branch(), 2);
+    apply(&mut text, history.redo().unwrap());
+    assert_eq!(text, "hello small");
+
+    // Moving the cursor in between edits breaks up the step
+    let mut delta = Delta::new();
+    delta.insert("!");
+    text.insert(0, '!');
+    history.record(
+        delta,
+        EditKind::Insert,
+        vec![Selection::cursor(0)],
+        vec![Selection::cursor(1)],
+    );
+    apply(&mut text, history.undo().unwrap());
+    assert_eq!(text, "hello small");
+}
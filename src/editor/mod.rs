@@ -1,7 +1,10 @@
 pub mod command_handler;
+pub mod delta;
 pub mod fonts;
 pub mod gon;
 pub mod highlighting;
+pub mod history;
+pub mod selection;
 pub mod text;
 pub mod types;
 pub mod view;
@@ -0,0 +1,136 @@
+use crate::util::*;
+use core::ops::Range;
+
+// A cursor, plus the other end of the selection when there is one. Both are
+// cursors into the whole document; the head is the end that moves.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Selection {
+    pub anchor: usize,
+    pub head: usize,
+}
+
+impl Selection {
+    pub const fn cursor(cursor: usize) -> Self {
+        return Self {
+            anchor: cursor,
+            head: cursor,
+        };
+    }
+
+    pub fn start(&self) -> usize {
+        return self.anchor.min(self.head);
+    }
+
+    pub fn end(&self) -> usize {
+        return self.anchor.max(self.head);
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        return self.start()..self.end();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.anchor == self.head;
+    }
+
+    // Whether the two should be merged into one. Selections that only touch
+    // are kept apart, unless one of them is just a cursor.
+    fn overlaps(&self, other: &Selection) -> bool {
+        let (first, second) = match self.start() <= other.start() {
+            true => (self, other),
+            false => (other, self),
+        };
+
+        if second.start() < first.end() || second.start() == first.start() {
+            return true;
+        }
+
+        return second.start() == first.end() && (first.is_empty() || second.is_empty());
+    }
+
+    // Covers both selections, facing the same way as this one
+    fn union(&self, other: &Selection) -> Selection {
+        let (start, end) = (self.start().min(other.start()), self.end().max(other.end()));
+        return match self.head >= self.anchor {
+            true => Selection {
+                anchor: start,
+                head: end,
+            },
+            false => Selection {
+                anchor: end,
+                head: start,
+            },
+        };
+    }
+}
+
+// Sorts the selections by where they are and merges the ones that overlap.
+// The first selection is the primary one, and whatever it gets merged into
+// stays first.
+pub fn merge_selections(selections: &[Selection]) -> Vec<Selection> {
+    let primary = match selections.first() {
+        Some(&primary) => primary,
+        None => return Vec::new(),
+    };
+
+    let mut sorted = selections.to_vec();
+    sorted.sort_by_key(|selection| (selection.start(), selection.end()));
+
+    let mut merged: Vec<(Selection, bool)> = Vec::with_capacity(sorted.len());
+    for selection in sorted {
+        let is_primary = selection == primary;
+        if let Some((last, last_is_primary)) = merged.last_mut() {
+            if last.overlaps(&selection) {
+                *last = match is_primary {
+                    true => selection.union(last),
+                    false => last.union(&selection),
+                };
+
+                *last_is_primary |= is_primary;
+                continue;
+            }
+        }
+
+        merged.push((selection, is_primary));
+    }
+
+    let primary = unwrap(merged.iter().position(|(_, is_primary)| *is_primary));
+    let mut selections = Vec::with_capacity(merged.len());
+    selections.push(merged.remove(primary).0);
+    selections.extend(merged.into_iter().map(|(selection, _)| selection));
+
+    return selections;
+}
+
+#[test]
+fn test_merge_selections() {
+    let selection = |anchor, head| Selection { anchor, head };
+
+    let selections = [
+        selection(10, 12),
+        selection(2, 0),
+        selection(11, 15),
+        Selection::cursor(15),
+        Selection::cursor(2),
+        selection(4, 6),
+        selection(6, 8),
+        Selection::cursor(20),
+        Selection::cursor(20),
+    ];
+
+    let merged = merge_selections(&selections);
+    assert_eq!(
+        merged,
+        [
+            selection(10, 15),
+            selection(2, 0),
+            selection(4, 6),
+            selection(6, 8),
+            Selection::cursor(20),
+        ]
+    );
+
+    // Backwards primary selections stay backwards
+    let merged = merge_selections(&[selection(5, 3), selection(1, 4)]);
+    assert_eq!(merged, [selection(5, 1)]);
+}
@@ -1,4 +1,13 @@
+use crate::editor::delta::*;
+use crate::editor::types::*;
 use crate::util::*;
+use btree::*;
+use core::ops::Range;
+use std::collections::VecDeque;
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete, UnicodeSegmentation};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+pub const DEFAULT_TAB_WIDTH: u32 = 4;
 
 // TODO clean this stuff up
 pub struct FlowConfig<Iter>
@@ -6,6 +15,10 @@ where
     Iter: Iterator<Item = char>,
 {
     text: Iter,
+    // The grapheme cluster that's being flowed, and chars after it that have
+    // already been read
+    cluster: String,
+    lookahead: VecDeque<char>,
     state: FlowState,
     params: FlowParams,
     needs_final: bool,
@@ -14,6 +27,19 @@ where
     //                  - Albert Liu, Jan 10, 2022 Mon 01:08 EST
     wrap_width: Option<u32>,
     vertical_bound: Option<u32>,
+
+    // Tabs go up to the next column that's a multiple of this
+    tab_width: u32,
+
+    wrap_mode: WrapMode,
+    indent_wraps: bool,
+
+    // Where the current row started, which is past 0 for indented wraps
+    row_start: u32,
+    // Whether a line can be broken before the next cluster
+    at_break: bool,
+    // The indentation of the current line, once the line gets past it
+    line_indent: Option<u32>,
 }
 
 #[derive(Clone, Copy)]
@@ -35,11 +61,14 @@ impl Default for FlowState {
     }
 }
 
+// Text gets flowed one extended grapheme cluster at a time, so `c` is just the
+// first char of the cluster, and `len` is how many chars are in it.
 #[derive(Clone, Copy)]
 pub struct FlowParams {
     pub write_len: u32,
     pub will_wrap: bool,
     pub c: char,
+    pub len: usize,
 }
 
 impl<Iter> FlowConfig<Iter>
@@ -49,19 +78,44 @@ where
     pub fn new(text: Iter, wrap_width: Option<u32>, vertical_bound: Option<u32>) -> Self {
         return Self {
             text,
+            cluster: String::new(),
+            lookahead: VecDeque::new(),
             state: Default::default(),
             params: FlowParams {
                 write_len: 0,
                 will_wrap: false,
                 c: ' ',
+                len: 0,
             },
             needs_final: false,
             wrap_width,
             vertical_bound,
+            tab_width: DEFAULT_TAB_WIDTH,
+            wrap_mode: WrapMode::Char,
+            indent_wraps: false,
+            row_start: 0,
+            at_break: true,
+            line_indent: None,
         };
     }
 
+    // With `indent_wraps`, rows that a line wraps onto start at the line's
+    // indentation, instead of at the left edge
+    pub fn with_wrap_mode(mut self, wrap_mode: WrapMode, indent_wraps: bool) -> Self {
+        self.wrap_mode = wrap_mode;
+        self.indent_wraps = indent_wraps;
+        return self;
+    }
+
+    pub fn with_tab_width(mut self, tab_width: u32) -> Self {
+        self.tab_width = tab_width.max(1);
+        return self;
+    }
+
+    // Flows whatever's left of the text, up to where the screen fills up, and
+    // returns where that ended
     pub fn finalize(mut self) -> FlowState {
+        for _ in &mut self {}
         self.complete_params();
 
         return self.state;
@@ -74,22 +128,117 @@ where
 
         self.needs_final = false;
 
+        self.state.index += self.params.len;
         self.state.pos.x += self.params.write_len;
-        if self.params.will_wrap {
+        if !self.params.will_wrap {
+            return;
+        }
+
+        if self.params.c == '\n' {
+            self.line_indent = None;
             self.state.pos.x = 0;
+            self.row_start = 0;
             self.state.pos.y += 1;
+            self.check_full();
+            return;
         }
 
+        self.wrap_row();
+    }
+
+    // Where rows that the current line wraps onto start
+    fn wrap_indent(&self) -> u32 {
+        return match (self.indent_wraps, self.line_indent, self.wrap_width) {
+            (true, Some(indent), Some(width)) => indent.min(width / 2),
+            _ => 0,
+        };
+    }
+
+    // Soft-wraps onto the next row
+    fn wrap_row(&mut self) {
+        self.row_start = self.wrap_indent();
+        self.state.pos.x = self.row_start;
+        self.state.pos.y += 1;
+        self.check_full();
+    }
+
+    fn check_full(&mut self) {
         if let Some(bound) = self.vertical_bound {
             if self.state.pos.y >= bound {
                 self.state.is_full = true;
             }
         }
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        return self.lookahead.pop_front().or_else(|| self.text.next());
+    }
+
+    // Where a cluster ends can't be known until the char after it shows up, so
+    // this reads one char past the cluster and holds onto it for next time.
+    fn next_cluster(&mut self) -> Option<char> {
+        self.cluster.clear();
+        let first = self.next_char()?;
+        self.cluster.push(first);
+
+        while let Some(c) = self.next_char() {
+            self.cluster.push(c);
+            if self.cluster.graphemes(true).nth(1).is_some() {
+                self.cluster.pop();
+                self.lookahead.push_front(c);
+                break;
+            }
+        }
+
+        return Some(first);
+    }
+
+    // How wide the word starting with the current cluster is, looking ahead
+    // at most `limit` cells. Words end at whitespace, after punctuation, and
+    // before wide chars, since lines can be broken between those.
+    fn word_width(&mut self, limit: u32) -> u32 {
+        let mut width = self.params.write_len;
+        if is_break_after(self.params.c) {
+            return width;
+        }
+
+        let mut index = 0;
+        while width <= limit {
+            let c = match self.lookahead.get(index) {
+                Some(&c) => c,
+                None => match self.text.next() {
+                    Some(c) => {
+                        self.lookahead.push_back(c);
+                        c
+                    }
+                    None => break,
+                },
+            };
+
+            let c_width = c.width().unwrap_or(0) as u32;
+            if c.is_whitespace() || c.is_control() || c_width > 1 {
+                break;
+            }
+
+            width += c_width;
+            index += 1;
+            if is_break_after(c) {
+                break;
+            }
+        }
 
-        self.state.index += 1;
+        return width;
     }
 }
 
+// Punctuation that a line can be broken after when word wrapping
+fn is_break_after(c: char) -> bool {
+    return matches!(
+        c,
+        '-' | '/' | '\\' | ',' | '.' | ';' | ':' | '!' | '?' | ')' | ']' | '}' | '|' | '&'
+    );
+}
+
 // TODO(design): This handles full newline-terminated lines a bit weirdly.
 // To be fair, Vim handles them a little bit weirdly too. Ideally we want
 // full newline terminated lines to only extend to an additional line
@@ -108,11 +257,18 @@ where
     fn next(&mut self) -> Option<Self::Item> {
         self.complete_params();
 
-        while let Some(c) = self.text.next() {
+        while let Some(first) = self.next_cluster() {
             if self.state.is_full {
                 return None;
             }
 
+            // "\r\n" is one cluster, and it's still a newline
+            let c = match self.cluster.ends_with('\n') {
+                true => '\n',
+                false => first,
+            };
+
+            self.params.len = self.cluster.chars().count();
             self.params.will_wrap = false;
             self.params.write_len = match c {
                 '\n' => {
@@ -121,16 +277,54 @@ where
 
                     0
                 }
-                '\t' => 2,
+                '\t' => {
+                    let len = self.tab_width - self.state.pos.x % self.tab_width;
+                    match self.wrap_width {
+                        Some(width) => len.min(width.saturating_sub(self.state.pos.x)),
+                        None => len,
+                    }
+                }
                 c if c.is_control() => {
-                    self.state.index += 1;
+                    self.state.index += self.params.len;
                     continue;
                 }
-                c => 1, // TODO grapheme stuffs
+                // Wide chars take up 2 cells, and combining marks and such
+                // take up none
+                c => self.cluster.width().min(2) as u32,
             };
             self.params.c = c;
 
+            if self.line_indent.is_none() && !c.is_whitespace() {
+                self.line_indent = Some(self.state.pos.x);
+            }
+
+            let is_word = !c.is_whitespace() && self.params.write_len == 1;
+            let at_break = self.at_break;
+            self.at_break = c.is_whitespace() || is_break_after(c) || !is_word;
+
             if let Some(width) = self.wrap_width {
+                // A wide char that doesn't fit at the end of the line goes on
+                // the next line, instead of getting split in half. When word
+                // wrapping, so do words, unless they're too long to fit on a
+                // line by themselves.
+                let needed = match self.wrap_mode {
+                    WrapMode::Word if is_word && at_break => self.word_width(width),
+                    _ => self.params.write_len,
+                };
+
+                let x = self.state.pos.x;
+                let is_too_long = self.wrap_indent() + needed > width;
+                let can_wrap = x > self.row_start && self.params.write_len > 0;
+                if can_wrap
+                    && x + needed > width
+                    && (!is_too_long || needed == self.params.write_len)
+                {
+                    self.wrap_row();
+                    if self.state.is_full {
+                        return None;
+                    }
+                }
+
                 if self.state.pos.x + self.params.write_len >= width {
                     self.params.will_wrap = true;
                 }
@@ -144,409 +338,1380 @@ where
     }
 }
 
-// use crate::util::*;
-// use btree::*;
-//
-// pub struct File {
-//     data: BTree<TextBuffer>,
-// }
-//
-// impl File {
-//     pub fn new() -> Self {
-//         let mut data = BTree::new();
-//         data.add(TextBuffer::new());
-//
-//         return Self { data };
-//     }
-//
-//     pub fn push(&mut self, text: &str) {
-//         let last = unwrap(self.data.last_idx());
-//         let offset = unwrap(self.data.get(last)).get_info().content_size;
-//
-//         self.insert_at(last, offset, text);
-//     }
-//
-//     pub fn insert(&mut self, idx: usize, text: &str) {
-//         let (idx, offset) = unwrap(self.data.key_leq_idx(idx, BufferInfo::content));
-//
-//         self.insert_at(idx, offset, text);
-//     }
-//
-//     pub fn delete(&mut self, begin: usize, end: usize) {
-//         if begin >= end {
-//             return;
-//         }
-//
-//         let mut len = end - begin;
-//         while len > 0 {
-//             let (idx, offset) = unwrap(self.data.key_idx(begin, BufferInfo::content));
-//             self.data.edit_or_remove(idx, |buf| {
-//                 let char_count = buf.char_count as usize;
-//                 if char_count > len {
-//                     let mut count = 0;
-//                     let mut byte_idx = None;
-//                     for (i, c) in buf.buffer.char_indices() {
-//                         if offset == count {
-//                             byte_idx = Some(i);
-//                             break;
-//                         }
-//
-//                         count += 1;
-//                     }
-//
-//                     let idx = match byte_idx {
-//                         Some(i) => i,
-//                         None => core::panic!("should this ever happen?"),
-//                     };
-//
-//                     for _ in 0..len {
-//                         buf.buffer.remove(idx);
-//                     }
-//
-//                     buf.char_count -= len as u16;
-//
-//                     len = 0;
-//                     return false;
-//                 }
-//
-//                 len -= char_count;
-//                 return true;
-//             });
-//         }
-//
-//         if self.data.len() == 0 {
-//             self.data.add(TextBuffer::new());
-//         }
-//     }
-//
-//     pub fn last_line_begin(&self) -> usize {
-//         let lines = self.newlines();
-//         if lines == 0 {
-//             return 0;
-//         }
-//
-//         return unwrap(self.cursor_for_line(lines - 1));
-//     }
-//
-//     fn insert_at(&mut self, idx: ElemIdx, mut offset: usize, text: &str) {
-//         let result = self.data.get_mut(idx, |buf| {
-//             let above = buf.split_at(offset);
-//             let mut iter = text.chars();
-//
-//             if buf.is_full() {
-//                 return (iter, above);
-//             }
-//
-//             for c in &mut iter {
-//                 let is_full = buf.insert(offset, c);
-//                 offset += 1;
-//
-//                 if is_full {
-//                     break;
-//                 }
-//             }
-//
-//             return (iter, above);
-//         });
-//
-//         let (mut remaining_chars, buf) = unwrap(result);
-//         let mut buf = match buf.is_empty() {
-//             true => buf,
-//             false => {
-//                 self.data.insert_after(idx, buf);
-//                 TextBuffer::new()
-//             }
-//         };
-//
-//         let mut idx = idx;
-//         for c in &mut remaining_chars {
-//             if buf.push(c) {
-//                 idx = self.data.insert_after(idx, buf);
-//                 buf = TextBuffer::new();
-//             }
-//         }
-//
-//         if !buf.is_empty() {
-//             self.data.insert_after(idx, buf);
-//         }
-//     }
-//
-//     pub fn newlines(&self) -> usize {
-//         return self.data.info().newline_count;
-//     }
-//
-//     pub fn len(&self) -> usize {
-//         return self.data.info().content_size;
-//     }
-//
-//     pub fn line_for_cursor(&self, idx: usize) -> Option<usize> {
-//         let (idx, remainder) = self.data.key_leq_idx(idx, BufferInfo::content)?;
-//         let lines_before = self.data.sum_until(idx, |_, info| info.newline_count)?;
-//         let bytes = self.data[idx].buffer.as_bytes().iter();
-//         let lines = lines_before + bytes.take(remainder).filter(|b| **b != b'\n').count();
-//
-//         return Some(lines);
-//     }
-//
-//     pub fn cursor_for_line(&self, line: usize) -> Option<usize> {
-//         let (idx, remainder) = self.data.key_leq_idx(line, BufferInfo::newlines)?;
-//         let cursor = self.data.sum_until(idx, |_, info| info.content_size)?;
-//
-//         return Some(cursor + remainder);
-//     }
-//
-//     pub fn end_cursor_for_line(&self, line: usize) -> usize {
-//         return match self.cursor_for_line(line + 1) {
-//             Some(cursor) => cursor,
-//             None => self.len(),
-//         };
-//     }
-//
-//     pub fn text_for_line<'a>(&'a self, line: usize) -> Option<LineIter<'a>> {
-//         let (idx, remainder) = self.data.key_leq_idx(line, BufferInfo::newlines)?;
-//         let idx = self.data.count_until(idx)?;
-//
-//         return Some(LineIter {
-//             file: self,
-//             idx,
-//             buffer_idx: remainder,
-//         });
-//     }
-//
-//     pub fn text_after_cursor<'a>(&'a self, cursor: usize) -> Option<TextIter<'a>> {
-//         if cursor == 0 {
-//             return Some(TextIter {
-//                 file: self,
-//                 idx: 0,
-//                 buffer_idx: 0,
-//             });
-//         }
-//
-//         let (idx, remainder) = self.data.key_idx(cursor, BufferInfo::content)?;
-//         let idx = self.data.count_until(idx)?;
-//
-//         return Some(TextIter {
-//             file: self,
-//             idx,
-//             buffer_idx: remainder,
-//         });
-//     }
-// }
-//
-// impl<'a> IntoIterator for &'a File {
-//     type Item = &'a str;
-//     type IntoIter = FileIter<'a>;
-//
-//     fn into_iter(self) -> FileIter<'a> {
-//         return FileIter { file: self, idx: 0 };
-//     }
-// }
-//
-// pub struct TextIter<'a> {
-//     file: &'a File,
-//     idx: usize,
-//     buffer_idx: usize,
-// }
-//
-// impl<'a> Iterator for TextIter<'a> {
-//     type Item = &'a str;
-//
-//     fn next(&mut self) -> Option<Self::Item> {
-//         let result = self.file.data.get(self.idx)?;
-//
-//         self.idx += 1;
-//         let bytes = &result.buffer.as_bytes()[self.buffer_idx..];
-//         self.buffer_idx = 0;
-//
-//         return Some(unsafe { core::str::from_utf8_unchecked(bytes) });
-//     }
-// }
-//
-// pub struct LineIter<'a> {
-//     file: &'a File,
-//     idx: usize,
-//     buffer_idx: usize,
-// }
-//
-// impl<'a> Iterator for LineIter<'a> {
-//     type Item = &'a str;
-//
-//     fn next(&mut self) -> Option<Self::Item> {
-//         let result = self.file.data.get(self.idx)?;
-//
-//         if result.newline_count == 0 {
-//             self.idx += 1;
-//             let bytes = &result.buffer.as_bytes()[self.buffer_idx..];
-//             self.buffer_idx = 0;
-//
-//             return Some(unsafe { core::str::from_utf8_unchecked(bytes) });
-//         }
-//
-//         self.idx = usize::MAX;
-//         let newline = result.buffer.as_bytes().iter().position(|&c| c == b'\n')?;
-//         let bytes = &result.buffer.as_bytes()[self.buffer_idx..newline];
-//         self.buffer_idx = 0;
-//
-//         return Some(unsafe { core::str::from_utf8_unchecked(bytes) });
-//     }
-// }
-//
-// pub struct FileIter<'a> {
-//     file: &'a File,
-//     idx: usize,
-// }
-//
-// impl<'a> Iterator for FileIter<'a> {
-//     type Item = &'a str;
-//
-//     fn next(&mut self) -> Option<Self::Item> {
-//         let result = self.file.data.get(self.idx);
-//         self.idx += 1;
-//         return result.map(|buf_view| &*buf_view.buffer);
-//     }
-// }
-//
-// struct TextBuffer {
-//     buffer: String,
-//     char_count: u16,
-//     newline_count: u16,
-// }
-//
-// impl TextBuffer {
-//     #[cfg(debug_assertions)]
-//     const MAX_LEN: usize = 64;
-//
-//     #[cfg(not(debug_assertions))]
-//     const MAX_LEN: usize = 1024;
-//
-//     pub fn new() -> Self {
-//         return Self {
-//             buffer: String::new(),
-//             char_count: 0,
-//             newline_count: 0,
-//         };
-//     }
-//
-//     pub fn is_empty(&self) -> bool {
-//         return self.char_count == 0;
-//     }
-//
-//     pub fn split_at(&mut self, offset: usize) -> Self {
-//         let buffer = self.buffer.clone();
-//
-//         let mut other = Self::new();
-//         self.buffer.clear();
-//         self.char_count = 0;
-//         self.newline_count = 0;
-//
-//         let mut idx = 0;
-//         let mut iter = buffer.chars();
-//         while idx < offset {
-//             let c = unwrap(iter.next());
-//             self.push(c);
-//             idx += 1;
-//         }
-//
-//         while let Some(c) = iter.next() {
-//             other.push(c);
-//         }
-//
-//         return other;
-//     }
-//
-//     pub fn is_full(&self) -> bool {
-//         return self.buffer.len() >= TextBuffer::MAX_LEN - 4;
-//     }
-//
-//     pub fn insert(&mut self, idx: usize, c: char) -> bool {
-//         if self.buffer.len() == 0 {
-//             self.buffer.reserve_exact(TextBuffer::MAX_LEN);
-//         }
-//
-//         let mut count = 0;
-//         let mut byte_idx = None;
-//         for (i, c) in self.buffer.char_indices() {
-//             if idx == count {
-//                 byte_idx = Some(i);
-//                 break;
-//             }
-//
-//             count += 1;
-//         }
-//
-//         // TODO this is sad. I guess all the methods are for byte-positions?
-//         // Which, great I guess. Super glad about that. Thanks Rust.
-//         //                                  - Albert Liu, Dec 20, 2021 Mon 03:39 EST
-//         match byte_idx {
-//             Some(i) => self.buffer.insert(i, c),
-//             None => {
-//                 if idx > count {
-//                     core::panic!(
-//                         "TextBuffer index was {} for count = {} (this is an editor error)",
-//                         idx,
-//                         count
-//                     );
-//                 }
-//
-//                 self.buffer.push(c)
-//             }
-//         }
-//
-//         self.char_count += 1;
-//         if c == '\n' {
-//             self.newline_count += 1;
-//         }
-//
-//         return self.is_full();
-//     }
-//
-//     pub fn push(&mut self, c: char) -> bool {
-//         if self.buffer.len() == 0 {
-//             self.buffer.reserve_exact(TextBuffer::MAX_LEN);
-//         }
-//
-//         self.buffer.push(c);
-//         self.char_count += 1;
-//         if c == '\n' {
-//             self.newline_count += 1;
-//         }
-//
-//         return self.is_full();
-//     }
-// }
-//
-// #[derive(Default, Clone, Copy)]
-// pub struct BufferInfo {
-//     content_size: usize,
-//     newline_count: usize,
-// }
-//
-// impl BufferInfo {
-//     fn content(self: BufferInfo) -> usize {
-//         return self.content_size;
-//     }
-//
-//     fn newlines(self: BufferInfo) -> usize {
-//         return self.newline_count;
-//     }
-// }
-//
-// impl BTreeInfo for BufferInfo {
-//     fn add(self, other: Self) -> Self {
-//         return BufferInfo {
-//             content_size: self.content_size + other.content_size,
-//             newline_count: self.newline_count + other.newline_count,
-//         };
-//     }
-// }
-//
-// impl BTreeItem for TextBuffer {
-//     type Info = BufferInfo;
-//
-//     fn get_info(&self) -> BufferInfo {
-//         return BufferInfo {
-//             content_size: self.char_count as usize,
-//             newline_count: self.newline_count as usize,
-//         };
-//     }
-// }
+// The text of a file, stored as a rope of small buffers in a BTree. Cursors
+// are char indices into the whole document; lines are separated by '\n', so
+// there's always one more line than there are newlines.
+//
+// Every change goes through a `Transaction`, and listeners get told about each
+// one as a `Delta` once it's done.
+pub struct Document {
+    data: BTree<TextBuffer>,
+    listeners: Vec<(ListenerId, Listener)>,
+    next_listener: usize,
+    anchors: Vec<AnchorState>,
+    next_anchor: usize,
+
+    // Overrides the tab width for the language, if it's set
+    tab_width: Option<u32>,
+}
+
+type Listener = Box<dyn FnMut(&Delta)>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ListenerId(usize);
+
+// A position in the document that moves along with the text around it as the
+// document changes, e.g. a cursor or a bookmark. The document keeps track of
+// where each one is; this is just a handle.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Anchor(usize);
+
+struct AnchorState {
+    anchor: Anchor,
+    cursor: usize,
+    gravity: Gravity,
+}
+
+impl Document {
+    pub fn new() -> Self {
+        let mut data = BTree::new();
+        data.add(TextBuffer::new());
+
+        return Self::with_data(data);
+    }
+
+    fn with_data(data: BTree<TextBuffer>) -> Self {
+        return Self {
+            data,
+            listeners: Vec::new(),
+            next_listener: 0,
+            anchors: Vec::new(),
+            next_anchor: 0,
+            tab_width: None,
+        };
+    }
+
+    pub fn tab_width(&self) -> Option<u32> {
+        return self.tab_width;
+    }
+
+    pub fn set_tab_width(&mut self, tab_width: Option<u32>) {
+        self.tab_width = tab_width;
+    }
+
+    pub fn subscribe(&mut self, listener: impl FnMut(&Delta) + 'static) -> ListenerId {
+        let id = ListenerId(self.next_listener);
+        self.next_listener += 1;
+        self.listeners.push((id, Box::new(listener)));
+
+        return id;
+    }
+
+    pub fn unsubscribe(&mut self, id: ListenerId) {
+        self.listeners.retain(|(listener, _)| *listener != id);
+    }
+
+    pub fn anchor(&mut self, cursor: usize, gravity: Gravity) -> Anchor {
+        let anchor = Anchor(self.next_anchor);
+        self.next_anchor += 1;
+
+        // Anchors are handed out in order and never reordered, so the list
+        // stays sorted for `anchor_index`
+        let cursor = cursor.min(self.len());
+        self.anchors.push(AnchorState {
+            anchor,
+            cursor,
+            gravity,
+        });
+
+        return anchor;
+    }
+
+    pub fn remove_anchor(&mut self, anchor: Anchor) {
+        if let Some(index) = self.anchor_index(anchor) {
+            self.anchors.remove(index);
+        }
+    }
+
+    // None if the anchor was removed
+    pub fn anchor_cursor(&self, anchor: Anchor) -> Option<usize> {
+        let index = self.anchor_index(anchor)?;
+        return Some(self.anchors[index].cursor);
+    }
+
+    pub fn move_anchor(&mut self, anchor: Anchor, cursor: usize) {
+        let len = self.len();
+        if let Some(index) = self.anchor_index(anchor) {
+            self.anchors[index].cursor = cursor.min(len);
+        }
+    }
+
+    fn anchor_index(&self, anchor: Anchor) -> Option<usize> {
+        let found = self
+            .anchors
+            .binary_search_by_key(&anchor.0, |state| state.anchor.0);
+        return found.ok();
+    }
+
+    fn notify(&mut self, delta: &Delta) {
+        if delta.is_empty() {
+            return;
+        }
+
+        for state in &mut self.anchors {
+            state.cursor = delta.transform(state.cursor, state.gravity);
+        }
+
+        for (_, listener) in &mut self.listeners {
+            listener(delta);
+        }
+    }
+
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        return Transaction {
+            document: self,
+            delta: Delta::new(),
+            committed: false,
+        };
+    }
+
+    pub fn push(&mut self, text: &str) -> Delta {
+        return self.insert(self.len(), text);
+    }
+
+    pub fn insert(&mut self, cursor: usize, text: &str) -> Delta {
+        let mut transaction = self.transaction();
+        transaction.insert(cursor, text);
+        return transaction.commit();
+    }
+
+    pub fn delete(&mut self, begin: usize, end: usize) -> Delta {
+        let mut transaction = self.transaction();
+        transaction.delete(begin, end);
+        return transaction.commit();
+    }
+
+    // Applies a delta that came from somewhere else, e.g. undo history
+    pub fn apply(&mut self, delta: &Delta) {
+        self.splice_delta(delta);
+        self.notify(delta);
+    }
+
+    fn splice_delta(&mut self, delta: &Delta) {
+        let mut cursor = 0;
+        for op in &delta.ops {
+            match op {
+                DeltaOp::Retain(len) => cursor += len,
+                DeltaOp::Insert(text) => {
+                    self.splice_insert(cursor, text);
+                    cursor += op.len();
+                }
+                DeltaOp::Delete(_) => self.splice_delete(cursor, cursor + op.len()),
+            }
+        }
+    }
+
+    fn splice_insert(&mut self, cursor: usize, text: &str) {
+        let cursor = cursor.min(self.len());
+        let (idx, offset) = unwrap(self.data.key_leq_idx::<Chars>(cursor));
+
+        self.insert_at(idx, offset, text);
+    }
+
+    fn splice_delete(&mut self, begin: usize, end: usize) {
+        let end = end.min(self.len());
+        if begin >= end {
+            return;
+        }
+
+        let (first, begin_offset) = unwrap(self.data.key_idx::<Chars>(begin));
+        let (last, end_offset) = unwrap(self.data.key_leq_idx::<Chars>(end));
+
+        if first == last {
+            self.data.edit_or_remove(first, |buf| {
+                buf.remove(begin_offset..end_offset);
+                return buf.is_empty();
+            });
+        } else {
+            let first_index = unwrap(self.data.count_until(first));
+            let last_index = unwrap(self.data.count_until(last));
+
+            // The last buffer goes first so that the indices of the ones in
+            // between are still right when they get drained
+            self.data.edit_or_remove(last, |buf| {
+                buf.remove(0..end_offset);
+                return buf.is_empty();
+            });
+
+            self.data.drain((first_index + 1)..last_index);
+
+            self.data.edit_or_remove(first, |buf| {
+                buf.remove(begin_offset..(buf.char_count as usize));
+                return buf.is_empty();
+            });
+        }
+
+        if self.data.len() == 0 {
+            self.data.add(TextBuffer::new());
+        }
+    }
+
+    fn insert_at(&mut self, idx: ElemIdx, offset: usize, text: &str) {
+        // Most inserts are someone typing, which almost always fits in the
+        // buffer that's already there
+        let fits = self.data.get_mut(idx, |buf| {
+            if buf.buffer.len() + text.len() > TextBuffer::MAX_LEN - 4 {
+                return false;
+            }
+
+            buf.insert_str(offset, text);
+            return true;
+        });
+
+        if unwrap(fits) {
+            return;
+        }
+
+        let mut chars = text.chars();
+        let tail = self.data.get_mut(idx, |buf| {
+            let tail = buf.split_off(offset);
+            while !buf.is_full() {
+                match chars.next() {
+                    Some(c) => buf.push(c),
+                    None => break,
+                };
+            }
+
+            return tail;
+        });
+
+        let tail = unwrap(tail);
+        let mut idx = idx;
+        let mut buf = TextBuffer::new();
+        for c in chars.chain(tail.buffer.chars()) {
+            if buf.push(c) {
+                let full = core::mem::take(&mut buf);
+                idx = unwrap(self.data.insert_after(idx, full));
+            }
+        }
+
+        if !buf.is_empty() {
+            self.data.insert_after(idx, buf);
+        }
+    }
+
+    pub fn newlines(&self) -> usize {
+        return self.data.info().newline_count;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.data.info().content_size;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    // Converts an offset in `From` units to `To` units, e.g. a byte offset
+    // from disk into a cursor. Offsets that aren't on a char boundary, like
+    // a byte in the middle of a multi-byte char or the second half of a UTF-16
+    // surrogate pair, don't have a conversion.
+    pub fn convert<From, To>(&self, offset: usize) -> Option<usize>
+    where
+        From: Metric<TextBuffer>,
+        To: Metric<TextBuffer>,
+    {
+        let converted = self.data.convert::<From, To>(offset)?;
+        if self.data.convert::<To, From>(converted)? != offset {
+            return None;
+        }
+
+        return Some(converted);
+    }
+
+    // The line that `offset` is on, and how far into the line it is, both in
+    // `M` units
+    pub fn line_col<M>(&self, offset: usize) -> Option<(usize, usize)>
+    where
+        M: Metric<TextBuffer>,
+    {
+        let cursor = self.convert::<M, Chars>(offset)?;
+        let line = self.line_for_cursor(cursor)?;
+        let line_begin = self.convert::<Chars, M>(self.cursor_for_line(line)?)?;
+
+        return Some((line, offset - line_begin));
+    }
+
+    // The inverse of `line_col`. Columns past the end of the line don't wrap
+    // around onto the next one.
+    pub fn offset_for_line_col<M>(&self, line: usize, col: usize) -> Option<usize>
+    where
+        M: Metric<TextBuffer>,
+    {
+        let begin = self.cursor_for_line(line)?;
+        let end = match self.cursor_for_line(line + 1) {
+            Some(next_line) => next_line - 1,
+            None => self.len(),
+        };
+
+        let (begin, end) = (
+            self.convert::<Chars, M>(begin)?,
+            self.convert::<Chars, M>(end)?,
+        );
+        let offset = begin + col;
+        if offset > end {
+            return None;
+        }
+
+        self.convert::<M, Chars>(offset)?;
+        return Some(offset);
+    }
+
+    pub fn last_line_begin(&self) -> usize {
+        return unwrap(self.cursor_for_line(self.newlines()));
+    }
+
+    pub fn line_for_cursor(&self, cursor: usize) -> Option<usize> {
+        return self.data.convert::<Chars, Newlines>(cursor);
+    }
+
+    pub fn cursor_for_line(&self, line: usize) -> Option<usize> {
+        if line == 0 {
+            return Some(0);
+        }
+
+        // Line `n` starts right after the n-th newline. Lots of buffers don't
+        // have any newlines at all, so this can't go through `convert`; it'd
+        // find the end of the buffer before the newline instead.
+        let (idx, remainder) = self.data.key_idx::<Newlines>(line - 1)?;
+        let buf = &self.data[idx];
+        let before = self.data.sum_until::<Chars>(idx)?;
+        let offset = Newlines::to_offset(buf, remainder + 1);
+
+        return Some(before + Chars::from_offset(buf, offset));
+    }
+
+    pub fn end_cursor_for_line(&self, line: usize) -> usize {
+        return match self.cursor_for_line(line + 1) {
+            Some(cursor) => cursor,
+            None => self.len(),
+        };
+    }
+
+    // The grapheme boundary before or after `cursor`, which can't be at the
+    // very beginning or the very end of the document. The segmenter asks for
+    // the buffers it needs one at a time, so this only ever looks at the few
+    // buffers around `cursor`, instead of the whole line.
+    fn grapheme_step(&self, cursor: usize, forward: bool) -> usize {
+        let byte = unwrap(self.convert::<Chars, Bytes>(cursor));
+        let total = self.data.info().byte_count;
+        let mut graphemes = GraphemeCursor::new(byte, total, true);
+
+        // At `total`, the buffer that `byte` is at the end of
+        let mut buffers = self.data.cursor();
+        buffers.seek::<Bytes>(byte.min(total - 1));
+
+        loop {
+            let buffer = &unwrap(buffers.get()).buffer;
+            let begin = buffers.offset::<Bytes>();
+            let found = match forward {
+                true => graphemes.next_boundary(buffer, begin),
+                false => graphemes.prev_boundary(buffer, begin),
+            };
+
+            match found {
+                Ok(found) => {
+                    let found = found.unwrap_or(if forward { total } else { 0 });
+                    return unwrap(self.convert::<Bytes, Chars>(found));
+                }
+                Err(GraphemeIncomplete::NextChunk) => {
+                    buffers.next();
+                }
+                Err(GraphemeIncomplete::PrevChunk) => {
+                    buffers.prev();
+                }
+                Err(GraphemeIncomplete::PreContext(end)) => {
+                    let (context, offset) = unwrap(self.data.key::<Bytes>(end - 1));
+                    let begin = end - offset - 1;
+                    graphemes.provide_context(&context.buffer[..=offset], begin);
+                }
+                Err(GraphemeIncomplete::InvalidOffset) => unreachable!(),
+            }
+        }
+    }
+
+    // The beginning of the grapheme cluster that `cursor` is in
+    pub fn grapheme_floor(&self, cursor: usize) -> usize {
+        if cursor >= self.len() {
+            return self.len();
+        }
+
+        return self.prev_grapheme(cursor + 1);
+    }
+
+    pub fn prev_grapheme(&self, cursor: usize) -> usize {
+        if cursor == 0 {
+            return 0;
+        }
+
+        return self.grapheme_step(cursor.min(self.len()), false);
+    }
+
+    pub fn next_grapheme(&self, cursor: usize) -> usize {
+        if cursor >= self.len() {
+            return self.len();
+        }
+
+        return self.grapheme_step(cursor, true);
+    }
+
+    pub fn text_for_line(&self, line: usize) -> Option<LineIter<'_>> {
+        let cursor = self.cursor_for_line(line)?;
+        let text = self.text_after_cursor(cursor)?;
+
+        return Some(LineIter { text, done: false });
+    }
+
+    pub fn text_after_cursor(&self, cursor: usize) -> Option<TextIter<'_>> {
+        let (iter, remainder) = self.data.iter_from_key_leq::<Chars>(cursor)?;
+        let offset = match iter.clone().next() {
+            Some(buf) => Chars::to_offset(buf, remainder),
+            None => 0,
+        };
+
+        return Some(TextIter { iter, offset });
+    }
+
+    pub fn chunks(&self) -> TextIter<'_> {
+        return TextIter {
+            iter: self.data.iter(),
+            offset: 0,
+        };
+    }
+
+    pub fn text_range(&self, range: Range<usize>) -> String {
+        let mut text = String::new();
+        let mut remaining = range.end.saturating_sub(range.start);
+        let chunks = match self.text_after_cursor(range.start) {
+            Some(chunks) => chunks,
+            None => return text,
+        };
+
+        for chunk in chunks {
+            for c in chunk.chars() {
+                if remaining == 0 {
+                    return text;
+                }
+
+                text.push(c);
+                remaining -= 1;
+            }
+        }
+
+        return text;
+    }
+
+    // Where the first copy of `needle` that begins at or after `from` begins.
+    // The search goes one buffer at a time, holding onto just enough of the
+    // end of the last one to catch a copy that straddles two buffers.
+    pub fn find(&self, needle: &str, from: usize) -> Option<usize> {
+        let keep = needle.chars().count().checked_sub(1)?;
+        let mut window = String::new();
+        let mut window_begin = from;
+        for chunk in self.text_after_cursor(from)? {
+            window.push_str(chunk);
+            if let Some(found) = window.find(needle) {
+                return Some(window_begin + window[..found].chars().count());
+            }
+
+            let dropped = window.chars().count().saturating_sub(keep);
+            let byte = match window.char_indices().nth(dropped) {
+                Some((byte, _)) => byte,
+                None => window.len(),
+            };
+
+            window.drain(..byte);
+            window_begin += dropped;
+        }
+
+        return None;
+    }
+
+    // The text that a view starting at `start_line` needs to fill `lines`
+    // lines. Lines that wrap can make this more than the view can show, but
+    // never less.
+    pub fn contents(&self, start_line: usize, lines: usize) -> SetContents {
+        let start_line = start_line.min(self.newlines());
+        let start = unwrap(self.cursor_for_line(start_line));
+        let end = match lines {
+            0 => start,
+            lines => self.end_cursor_for_line(start_line + lines - 1),
+        };
+
+        return SetContents {
+            start,
+            start_line,
+            text: self.text_range(start..end),
+        };
+    }
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl From<&str> for Document {
+    fn from(text: &str) -> Self {
+        let mut buffers = Vec::new();
+        let mut buf = TextBuffer::new();
+        for c in text.chars() {
+            if buf.push(c) {
+                buffers.push(core::mem::take(&mut buf));
+            }
+        }
+
+        if !buf.is_empty() || buffers.is_empty() {
+            buffers.push(buf);
+        }
+
+        return Self::with_data(buffers.into_iter().collect());
+    }
+}
+
+// Groups edits into one change. Edits go into the document right away, so each
+// one's positions are in terms of the text after the edits before it; the
+// listeners hear about all of them at once, as a single delta, when the
+// transaction commits. Anchors don't move until then either. Dropping a
+// transaction without committing it takes its edits back out, and nobody hears
+// about them.
+pub struct Transaction<'a> {
+    document: &'a mut Document,
+    delta: Delta,
+    committed: bool,
+}
+
+impl<'a> Transaction<'a> {
+    pub fn document(&self) -> &Document {
+        return self.document;
+    }
+
+    // Everything this transaction has done so far
+    pub fn delta(&self) -> &Delta {
+        return &self.delta;
+    }
+
+    pub fn insert(&mut self, cursor: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        let cursor = cursor.min(self.document.len());
+        self.document.splice_insert(cursor, text);
+
+        let mut edit = Delta::new();
+        edit.retain(cursor);
+        edit.insert(text);
+        self.delta = self.delta.compose(&edit);
+    }
+
+    pub fn delete(&mut self, begin: usize, end: usize) {
+        let end = end.min(self.document.len());
+        if begin >= end {
+            return;
+        }
+
+        let text = self.document.text_range(begin..end);
+        self.document.splice_delete(begin, end);
+
+        let mut edit = Delta::new();
+        edit.retain(begin);
+        edit.delete(&text);
+        self.delta = self.delta.compose(&edit);
+    }
+
+    pub fn commit(mut self) -> Delta {
+        self.committed = true;
+        let delta = core::mem::take(&mut self.delta);
+        self.document.notify(&delta);
+
+        return delta;
+    }
+}
+
+impl<'a> Drop for Transaction<'a> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let undo = self.delta.invert();
+            self.document.splice_delta(&undo);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Document {
+    type Item = &'a str;
+    type IntoIter = TextIter<'a>;
+
+    fn into_iter(self) -> TextIter<'a> {
+        return self.chunks();
+    }
+}
+
+// Chunks of text, in order. Chunks never cross a buffer boundary, and they're
+// never empty.
+#[derive(Clone)]
+pub struct TextIter<'a> {
+    iter: Iter<'a, TextBuffer>,
+    offset: usize,
+}
+
+impl<'a> Iterator for TextIter<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let buf = self.iter.next()?;
+            let text = &buf.buffer[self.offset..];
+            self.offset = 0;
+
+            if !text.is_empty() {
+                return Some(text);
+            }
+        }
+    }
+}
+
+// Chunks of text up to the end of a line, not including the newline
+pub struct LineIter<'a> {
+    text: TextIter<'a>,
+    done: bool,
+}
+
+impl<'a> Iterator for LineIter<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let chunk = self.text.next()?;
+        return match chunk.find('\n') {
+            Some(newline) => {
+                self.done = true;
+                Some(&chunk[..newline])
+            }
+            None => Some(chunk),
+        };
+    }
+}
+
+#[derive(Clone)]
+pub struct TextBuffer {
+    buffer: String,
+    char_count: u16,
+    utf16_count: u16,
+    newline_count: u16,
+}
+
+impl TextBuffer {
+    #[cfg(debug_assertions)]
+    const MAX_LEN: usize = 64;
+
+    #[cfg(not(debug_assertions))]
+    const MAX_LEN: usize = 1024;
+
+    pub fn new() -> Self {
+        return Self {
+            buffer: String::new(),
+            char_count: 0,
+            utf16_count: 0,
+            newline_count: 0,
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.char_count == 0;
+    }
+
+    pub fn is_full(&self) -> bool {
+        return self.buffer.len() >= TextBuffer::MAX_LEN - 4;
+    }
+
+    // TODO this is sad. I guess all the methods are for byte-positions?
+    // Which, great I guess. Super glad about that. Thanks Rust.
+    //                                  - Albert Liu, Dec 20, 2021 Mon 03:39 EST
+    fn byte_offset(&self, offset: usize) -> usize {
+        return match self.buffer.char_indices().nth(offset) {
+            Some((i, _)) => i,
+            None => {
+                if offset > self.char_count as usize {
+                    core::panic!(
+                        "TextBuffer index was {} for count = {} (this is an editor error)",
+                        offset,
+                        self.char_count
+                    );
+                }
+
+                self.buffer.len()
+            }
+        };
+    }
+
+    fn recount(&mut self) {
+        self.char_count = self.buffer.chars().count() as u16;
+        self.utf16_count = self.buffer.chars().map(char::len_utf16).sum::<usize>() as u16;
+        self.newline_count = self.buffer.bytes().filter(|&b| b == b'\n').count() as u16;
+    }
+
+    pub fn split_off(&mut self, offset: usize) -> Self {
+        let at = self.byte_offset(offset);
+        let mut other = Self {
+            buffer: self.buffer.split_off(at),
+            char_count: 0,
+            utf16_count: 0,
+            newline_count: 0,
+        };
+
+        self.recount();
+        other.recount();
+
+        return other;
+    }
+
+    pub fn insert_str(&mut self, offset: usize, text: &str) {
+        if self.buffer.is_empty() {
+            self.buffer.reserve_exact(TextBuffer::MAX_LEN);
+        }
+
+        let at = self.byte_offset(offset);
+        self.buffer.insert_str(at, text);
+        self.recount();
+    }
+
+    pub fn remove(&mut self, range: Range<usize>) {
+        let (begin, end) = (self.byte_offset(range.start), self.byte_offset(range.end));
+        self.buffer.replace_range(begin..end, "");
+        self.recount();
+    }
+
+    pub fn push(&mut self, c: char) -> bool {
+        if self.buffer.is_empty() {
+            self.buffer.reserve_exact(TextBuffer::MAX_LEN);
+        }
+
+        self.buffer.push(c);
+        self.char_count += 1;
+        self.utf16_count += c.len_utf16() as u16;
+        if c == '\n' {
+            self.newline_count += 1;
+        }
+
+        return self.is_full();
+    }
+}
+
+impl Default for TextBuffer {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct BufferInfo {
+    // In chars
+    content_size: usize,
+    byte_count: usize,
+    utf16_count: usize,
+    newline_count: usize,
+}
+
+impl BTreeInfo for BufferInfo {
+    fn add(self, other: Self) -> Self {
+        return BufferInfo {
+            content_size: self.content_size + other.content_size,
+            byte_count: self.byte_count + other.byte_count,
+            utf16_count: self.utf16_count + other.utf16_count,
+            newline_count: self.newline_count + other.newline_count,
+        };
+    }
+}
+
+impl BTreeItem for TextBuffer {
+    type Info = BufferInfo;
+
+    fn get_info(&self) -> BufferInfo {
+        return BufferInfo {
+            content_size: self.char_count as usize,
+            byte_count: self.buffer.len(),
+            utf16_count: self.utf16_count as usize,
+            newline_count: self.newline_count as usize,
+        };
+    }
+}
+
+// Cursor positions, i.e. chars
+pub struct Chars;
+
+impl Metric<TextBuffer> for Chars {
+    fn measure(_count: usize, info: BufferInfo) -> usize {
+        return info.content_size;
+    }
+
+    fn to_offset(buf: &TextBuffer, value: usize) -> usize {
+        return buf.byte_offset(value);
+    }
+
+    fn from_offset(buf: &TextBuffer, offset: usize) -> usize {
+        return buf.buffer[..floor_char_boundary(&buf.buffer, offset)]
+            .chars()
+            .count();
+    }
+}
+
+// UTF-8 bytes, i.e. offsets into the file on disk
+pub struct Bytes;
+
+impl Metric<TextBuffer> for Bytes {
+    fn measure(_count: usize, info: BufferInfo) -> usize {
+        return info.byte_count;
+    }
+}
+
+// UTF-16 code units, which is what JS and LSP use
+pub struct Utf16;
+
+impl Metric<TextBuffer> for Utf16 {
+    fn measure(_count: usize, info: BufferInfo) -> usize {
+        return info.utf16_count;
+    }
+
+    fn to_offset(buf: &TextBuffer, value: usize) -> usize {
+        let mut units = 0;
+        for (i, c) in buf.buffer.char_indices() {
+            units += c.len_utf16();
+            if units > value {
+                return i;
+            }
+        }
+
+        return buf.buffer.len();
+    }
+
+    fn from_offset(buf: &TextBuffer, offset: usize) -> usize {
+        return buf.buffer[..floor_char_boundary(&buf.buffer, offset)]
+            .chars()
+            .map(char::len_utf16)
+            .sum();
+    }
+}
+
+// Offsets that land in the middle of a char get rounded down, and then
+// `Document::convert` notices that they don't convert back
+fn floor_char_boundary(text: &str, mut offset: usize) -> usize {
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+
+    return offset;
+}
+
+// A newline's position is the position right after it, i.e. the beginning of
+// the line that it starts
+pub struct Newlines;
+
+impl Metric<TextBuffer> for Newlines {
+    fn measure(_count: usize, info: BufferInfo) -> usize {
+        return info.newline_count;
+    }
+
+    fn to_offset(buf: &TextBuffer, value: usize) -> usize {
+        if value == 0 {
+            return 0;
+        }
+
+        let mut newlines = buf.buffer.match_indices('\n');
+        return match newlines.nth(value - 1) {
+            Some((i, _)) => i + 1,
+            None => buf.buffer.len(),
+        };
+    }
+
+    fn from_offset(buf: &TextBuffer, offset: usize) -> usize {
+        return buf.buffer[..offset].bytes().filter(|&b| b == b'\n').count();
+    }
+}
+
+#[test]
+fn test_document_edits() {
+    // Buffers are tiny in debug builds, so this crosses lots of boundaries
+    let mut doc = Document::new();
+    let mut model = String::new();
+    let mut seed = 12345u64;
+    let mut next = move |limit: usize| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        return (seed >> 33) as usize % limit;
+    };
+
+    const WORDS: [&str; 6] = [
+        "hello",
+        " ",
+        "\n",
+        "wörld ",
+        "ünïcødé\n\n",
+        "a longer piece of text that doesn't fit in one buffer\n",
+    ];
+
+    for step in 0..2000 {
+        let len = model.chars().count();
+        if next(3) == 0 && len > 0 {
+            let begin = next(len);
+            let end = (begin + next(100)).min(len);
+            doc.delete(begin, end);
+
+            let (begin, end) = (byte_index(&model, begin), byte_index(&model, end));
+            model.replace_range(begin..end, "");
+        } else {
+            let cursor = next(len + 1);
+            let text = WORDS[next(WORDS.len())];
+            doc.insert(cursor, text);
+            model.insert_str(byte_index(&model, cursor), text);
+        }
+
+        assert_eq!(doc.len(), model.chars().count(), "step {}", step);
+        assert_eq!(doc.newlines(), model.matches('\n').count(), "step {}", step);
+        assert_eq!(
+            doc.convert::<Chars, Bytes>(doc.len()),
+            Some(model.len()),
+            "step {}",
+            step
+        );
+        assert_eq!(doc.chunks().collect::<String>(), model, "step {}", step);
+    }
+
+    doc.delete(0, doc.len());
+    assert_eq!(doc.len(), 0);
+    assert_eq!(doc.chunks().count(), 0);
+
+    doc.push("abc");
+    doc.push("def");
+    assert_eq!(doc.chunks().collect::<String>(), "abcdef");
+}
+
+#[test]
+fn test_document_lines() {
+    let text = "first line\n\nthird ünïcødé line, which is long enough to need a few buffers\nlast";
+    let doc = Document::from(text);
+    let lines: Vec<&str> = text.split('\n').collect();
+
+    assert_eq!(doc.newlines(), lines.len() - 1);
+    assert_eq!(doc.cursor_for_line(lines.len()), None);
+    assert_eq!(doc.last_line_begin(), text.chars().count() - "last".len());
+
+    let mut cursor = 0;
+    for (line, expected) in lines.iter().enumerate() {
+        assert_eq!(doc.cursor_for_line(line), Some(cursor));
+        assert_eq!(
+            doc.text_for_line(line).unwrap().collect::<String>(),
+            *expected
+        );
+
+        let len = expected.chars().count();
+        for c in cursor..=(cursor + len) {
+            assert_eq!(doc.line_for_cursor(c), Some(line));
+        }
+
+        cursor += len + 1;
+        assert_eq!(doc.end_cursor_for_line(line), cursor.min(doc.len()));
+    }
+
+    let after: String = doc.text_after_cursor(5).unwrap().collect();
+    assert_eq!(after, text.chars().skip(5).collect::<String>());
+    assert_eq!(doc.text_after_cursor(doc.len()).unwrap().count(), 0);
+
+    let contents = doc.contents(1, 2);
+    assert_eq!(contents.start, 11);
+    assert_eq!(contents.start_line, 1);
+    assert_eq!(
+        contents.text,
+        text[11..].split_inclusive('\n').take(2).collect::<String>()
+    );
+}
+
+#[test]
+fn test_document_conversions() {
+    let text = "aé€𝄞\nplain ascii that's long enough to span a buffer or two\n\n𝄞𝄞 é\n";
+    let doc = Document::from(text);
+
+    let (mut chars, mut utf16) = (0, 0);
+    let mut line_begin = (0, 0, 0);
+    let mut line = 0;
+    for (byte, c) in text.char_indices().chain([(text.len(), '\0')]) {
+        assert_eq!(doc.convert::<Bytes, Chars>(byte), Some(chars));
+        assert_eq!(doc.convert::<Chars, Bytes>(chars), Some(byte));
+        assert_eq!(doc.convert::<Chars, Utf16>(chars), Some(utf16));
+        assert_eq!(doc.convert::<Utf16, Bytes>(utf16), Some(byte));
+
+        let (line_byte, line_char, line_utf16) = line_begin;
+        assert_eq!(doc.line_col::<Bytes>(byte), Some((line, byte - line_byte)));
+        assert_eq!(
+            doc.line_col::<Chars>(chars),
+            Some((line, chars - line_char))
+        );
+        assert_eq!(
+            doc.line_col::<Utf16>(utf16),
+            Some((line, utf16 - line_utf16))
+        );
+        assert_eq!(
+            doc.offset_for_line_col::<Bytes>(line, byte - line_byte),
+            Some(byte)
+        );
+        assert_eq!(
+            doc.offset_for_line_col::<Utf16>(line, utf16 - line_utf16),
+            Some(utf16)
+        );
+
+        for inside in 1..c.len_utf8() {
+            assert_eq!(doc.convert::<Bytes, Chars>(byte + inside), None);
+        }
+
+        if c.len_utf16() == 2 {
+            assert_eq!(doc.convert::<Utf16, Chars>(utf16 + 1), None);
+        }
+
+        chars += 1;
+        utf16 += c.len_utf16();
+        if c == '\n' {
+            line += 1;
+            line_begin = (byte + 1, chars, utf16);
+        }
+    }
+
+    assert_eq!(doc.convert::<Bytes, Chars>(text.len() + 1), None);
+    assert_eq!(doc.offset_for_line_col::<Chars>(0, 5), None);
+    assert_eq!(doc.offset_for_line_col::<Chars>(line + 1, 0), None);
+    assert_eq!(Document::new().line_col::<Utf16>(0), Some((0, 0)));
+}
+
+#[test]
+fn test_document_find() {
+    let filler = "-".repeat(TextBuffer::MAX_LEN - 10);
+    let text = format!("needle {filler}ne\u{e9}dle needle");
+    let doc = Document::from(&*text);
+    // The second copy straddles the first two buffers
+    let second = filler.len() + 7;
+
+    assert_eq!(doc.find("needle", 0), Some(0));
+    assert_eq!(doc.find("needle", 1), Some(second + 7));
+    assert_eq!(doc.find("n\u{e9}e", 0), None);
+    assert_eq!(doc.find("ne\u{e9}dle", 1), Some(second));
+    assert_eq!(doc.find("e\u{e9}d", second + 2), None);
+    assert_eq!(doc.find("", 0), None);
+}
+
+#[test]
+fn test_document_transactions() {
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    let mut doc = Document::from("one two three");
+    let deltas = Rc::new(RefCell::new(Vec::new()));
+
+    let seen = deltas.clone();
+    let listener = doc.subscribe(move |delta| seen.borrow_mut().push(delta.clone()));
+
+    let mut transaction = doc.transaction();
+    transaction.delete(0, 4);
+    transaction.insert(3, ", 2,");
+    transaction.insert(0, "1: ");
+    assert_eq!(
+        transaction.document().chunks().collect::<String>(),
+        "1: two, 2, three"
+    );
+    assert!(deltas.borrow().is_empty());
+
+    let delta = transaction.commit();
+    let mut expected = Delta::new();
+    expected.insert("1: ");
+    expected.delete("one ");
+    expected.retain(3);
+    expected.insert(", 2,");
+    assert_eq!(delta, expected);
+    assert_eq!(*deltas.borrow(), [expected.clone()]);
+
+    // Abandoned transactions put everything back, and don't tell anyone
+    let mut transaction = doc.transaction();
+    transaction.delete(0, 100);
+    transaction.insert(0, "gone");
+    drop(transaction);
+    assert_eq!(doc.chunks().collect::<String>(), "1: two, 2, three");
+    assert_eq!(deltas.borrow().len(), 1);
+
+    doc.apply(&expected.invert());
+    assert_eq!(doc.chunks().collect::<String>(), "one two three");
+    assert_eq!(deltas.borrow().len(), 2);
+
+    doc.unsubscribe(listener);
+    doc.insert(0, "zero ");
+    doc.delete(0, 0);
+    assert_eq!(deltas.borrow().len(), 2);
+
+    // Inserting past the end appends, same as deleting past the end stops
+    // at the end
+    let mut doc = Document::from("ab");
+    let delta = doc.insert(10, "!");
+    assert_eq!(doc.chunks().collect::<String>(), "ab!");
+
+    let mut expected = Delta::new();
+    expected.retain(2);
+    expected.insert("!");
+    assert_eq!(delta, expected);
+
+    let mut transaction = doc.transaction();
+    transaction.insert(100, "?");
+    transaction.delete(100, 200);
+    assert_eq!(transaction.document().chunks().collect::<String>(), "ab!?");
+    transaction.commit();
+}
+
+#[test]
+fn test_document_anchors() {
+    let mut doc = Document::from("0123456789");
+    let left = doc.anchor(5, Gravity::Left);
+    let right = doc.anchor(5, Gravity::Right);
+    let end = doc.anchor(100, Gravity::Right);
+    let gone = doc.anchor(8, Gravity::Left);
+
+    doc.insert(5, "ab");
+    assert_eq!(doc.anchor_cursor(left), Some(5));
+    assert_eq!(doc.anchor_cursor(right), Some(7));
+    assert_eq!(doc.anchor_cursor(end), Some(12));
+
+    doc.insert(0, "xyz");
+    doc.delete(1, 3);
+    assert_eq!(doc.anchor_cursor(left), Some(6));
+    assert_eq!(doc.anchor_cursor(right), Some(8));
+    assert_eq!(doc.anchor_cursor(gone), Some(11));
+
+    // Anchors inside of deleted text end up where it was
+    doc.delete(9, 12);
+    assert_eq!(doc.anchor_cursor(gone), Some(9));
+    assert_eq!(doc.anchor_cursor(end), Some(10));
+
+    // Taking back a transaction leaves anchors alone
+    let mut transaction = doc.transaction();
+    transaction.delete(0, 10);
+    drop(transaction);
+    assert_eq!(doc.anchor_cursor(left), Some(6));
+
+    doc.move_anchor(left, 2);
+    doc.remove_anchor(gone);
+    doc.insert(0, "!");
+    assert_eq!(doc.anchor_cursor(left), Some(3));
+    assert_eq!(doc.anchor_cursor(gone), None);
+    assert_eq!(doc.anchor_cursor(end), Some(11));
+}
+
+#[cfg(test)]
+fn byte_index(text: &str, cursor: usize) -> usize {
+    return text
+        .char_indices()
+        .nth(cursor)
+        .map(|(i, _)| i)
+        .unwrap_or(text.len());
+}
+
+#[test]
+fn test_flow_graphemes() {
+    // "é" as 'e' plus a combining accent, a ZWJ family, and a "\r\n"
+    let text = "e\u{301}x\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}!\r\nab";
+    let mut config = FlowConfig::new(text.chars(), Some(10), None);
+    let mut flowed = Vec::new();
+    for (state, params) in &mut config {
+        flowed.push((state.index, state.pos.x, state.pos.y, params.len));
+    }
+
+    let state = config.finalize();
+    assert_eq!(
+        flowed,
+        [
+            (0, 0, 0, 2),
+            (2, 1, 0, 1),
+            (3, 2, 0, 5),
+            (8, 4, 0, 1),
+            (9, 5, 0, 2),
+            (11, 0, 1, 1),
+            (12, 1, 1, 1),
+        ]
+    );
+    assert_eq!(state.index, text.chars().count());
+    assert_eq!(state.newline_count, 1);
+
+    let document = Document::from(text);
+    let mut cursor = 0;
+    let mut boundaries = vec![cursor];
+    while cursor < document.len() {
+        cursor = document.next_grapheme(cursor);
+        boundaries.push(cursor);
+    }
+    assert_eq!(boundaries, [0, 2, 3, 8, 9, 11, 12, 13]);
+
+    assert_eq!(document.prev_grapheme(8), 3);
+    assert_eq!(document.prev_grapheme(11), 9);
+    assert_eq!(document.prev_grapheme(1), 0);
+    assert_eq!(document.grapheme_floor(6), 3);
+    assert_eq!(document.grapheme_floor(10), 9);
+    assert_eq!(document.grapheme_floor(13), 13);
+
+    // Clusters that straddle two buffers, including flags, which need to look
+    // back at the flags before them
+    let filler = "-".repeat(TextBuffer::MAX_LEN - 3);
+    let text = format!("{filler}\u{1F1FA}\u{1F1F8}\u{1F1FA}\u{1F1F8}e\u{301}\n\r\n");
+    let document = Document::from(&*text);
+    let begin = filler.len();
+    let mut cursor = begin;
+    let mut boundaries = vec![cursor];
+    while cursor < document.len() {
+        cursor = document.next_grapheme(cursor);
+        boundaries.push(cursor);
+    }
+    assert_eq!(boundaries, [0, 2, 4, 6, 7, 9].map(|b| begin + b));
+
+    assert_eq!(document.prev_grapheme(begin + 4), begin + 2);
+    assert_eq!(document.grapheme_floor(begin + 3), begin + 2);
+    assert_eq!(document.grapheme_floor(begin + 8), begin + 7);
+    assert_eq!(document.prev_grapheme(begin), begin - 1);
+}
+
+#[test]
+fn test_flow_wide_chars() {
+    let text = "日本語x\u{200B}y";
+    let mut config = FlowConfig::new(text.chars(), Some(5), None);
+    let mut flowed = Vec::new();
+    for (state, params) in &mut config {
+        flowed.push((state.index, state.pos.x, state.pos.y, params.write_len));
+    }
+
+    // The third one doesn't fit at the end of the first line
+    assert_eq!(
+        flowed,
+        [
+            (0, 0, 0, 2),
+            (1, 2, 0, 2),
+            (2, 0, 1, 2),
+            (3, 2, 1, 1),
+            (4, 3, 1, 0),
+            (5, 3, 1, 1),
+        ]
+    );
+
+    let mut config = FlowConfig::new(text.chars(), Some(5), Some(1));
+    for _ in &mut config {}
+    let state = config.finalize();
+    assert!(state.is_full);
+    assert_eq!(state.index, 2);
+}
+
+#[test]
+fn test_flow_tabs() {
+    let flow = |text: &str, tab_width| {
+        let mut config = FlowConfig::new(text.chars(), Some(10), None).with_tab_width(tab_width);
+        let mut flowed = Vec::new();
+        for (state, params) in &mut config {
+            flowed.push((state.pos.x, state.pos.y, params.write_len));
+        }
+
+        return flowed;
+    };
+
+    // Tabs line up on the next tab stop, wherever they start
+    assert_eq!(
+        flow("\ta\tbc\t", 4),
+        [
+            (0, 0, 4),
+            (4, 0, 1),
+            (5, 0, 3),
+            (8, 0, 1),
+            (9, 0, 1),
+            (0, 1, 4)
+        ]
+    );
+    assert_eq!(
+        flow("ab\t\tc", 3),
+        [(0, 0, 1), (1, 0, 1), (2, 0, 1), (3, 0, 3), (6, 0, 1)]
+    );
+
+    // Near the end of the line, a tab just fills out the rest of it
+    assert_eq!(flow("12345678\tx", 4)[8..], [(8, 0, 2), (0, 1, 1)]);
+}
+
+#[test]
+fn test_flow_word_wrap() {
+    let layout = |text: &str, width, mode, indent| {
+        let config = FlowConfig::new(text.chars(), Some(width), None);
+        let mut config = config.with_wrap_mode(mode, indent);
+        let mut rows: Vec<Vec<char>> = Vec::new();
+        for (state, params) in &mut config {
+            let (x, y) = (state.pos.x as usize, state.pos.y as usize);
+            if rows.len() <= y {
+                rows.resize(y + 1, Vec::new());
+            }
+
+            if params.c != '\n' {
+                let row = &mut rows[y];
+                row.resize(row.len().max(x), ' ');
+                row.push(params.c);
+            }
+        }
+
+        let rows = rows
+            .into_iter()
+            .map(|row| row.into_iter().collect::<String>());
+        return rows
+            .map(|row| row.trim_end().to_string())
+            .collect::<Vec<_>>();
+    };
+
+    let text = "hello world foo";
+    assert_eq!(
+        layout(text, 8, WrapMode::Char, false),
+        ["hello wo", "rld foo"]
+    );
+    assert_eq!(
+        layout(text, 8, WrapMode::Word, false),
+        ["hello", "world", "foo"]
+    );
+
+    // Words that are too long for a line get broken up anyways
+    let text = "a verylongword b";
+    assert_eq!(
+        layout(text, 6, WrapMode::Word, false),
+        ["a very", "longwo", "rd b"]
+    );
+
+    let text = "foo-bar,baz";
+    assert_eq!(
+        layout(text, 6, WrapMode::Word, false),
+        ["foo-", "bar,", "baz"]
+    );
+
+    let text = "  one two three\nfour";
+    assert_eq!(
+        layout(text, 10, WrapMode::Word, true),
+        ["  one two", "  three", "four"]
+    );
+    assert_eq!(
+        layout(text, 10, WrapMode::Word, false),
+        ["  one two", "three", "four"]
+    );
+
+    let config = FlowConfig::new("abc def".chars(), Some(5), Some(1));
+    let mut config = config.with_wrap_mode(WrapMode::Word, false);
+    for _ in &mut config {}
+    let state = config.finalize();
+    assert!(state.is_full);
+    assert_eq!(state.index, 4);
+}
@@ -23,6 +23,10 @@ pub struct Glyph {
     pub bot_right_2: Point2<u32>,
 }
 
+// In the text of a `DrawView`, the cell after a wide char holds this instead
+// of a char of its own, so that the wide char's glyph can go across both cells
+pub const WIDE_CHAR_TAIL: char = '\0';
+
 pub struct TextShaderInput<'a> {
     pub is_lines: bool,
     pub atlas: Option<&'a [u8]>,
@@ -45,6 +49,8 @@ pub enum HLAction {
     None,
 }
 
+// The part of a document that a view shows, starting at the beginning of line
+// `start_line`, which is at char `start`
 #[cfg_attr(debug_assertions, derive(PartialEq))]
 pub struct SetContents {
     pub start: usize,
@@ -52,6 +58,17 @@ pub struct SetContents {
     pub text: String,
 }
 
+// How lines that are too long for the screen get broken up
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WrapMode {
+    // Wherever the line runs out of room
+    Char,
+    // At whitespace and after punctuation, or wherever the line runs out of
+    // room when a word's too long to fit on a line by itself
+    Word,
+}
+
+#[derive(Clone, Copy)]
 #[cfg_attr(debug_assertions, derive(PartialEq))]
 pub enum Direction {
     Up,
@@ -99,10 +116,34 @@ pub fn for_view(command: ViewCommand) -> TedCommand {
 pub enum ViewCommand {
     CursorMove(Direction),
     ToggleCursorBlink,
-    Insert { text: String },
+    Insert {
+        text: String,
+    },
     DeleteAfterCursor,
-    FlowCursor { index: usize },
-    SetContents(SetContents),
+    ExtendSelection(Direction),
+    AddCursorAbove,
+    AddCursorBelow,
+    AddNextOccurrence,
+    SplitSelectionIntoLines,
+    SingleCursor,
+    SetTabWidth {
+        tab_width: Option<u32>,
+    },
+    SetWrap {
+        mode: WrapMode,
+        indent: bool,
+        marker: Option<char>,
+    },
+    Undo,
+    Redo,
+    NextUndoBranch,
+    FlowCursor {
+        index: usize,
+    },
+    // Scrolls so that the view starts at the beginning of `start_line`
+    SetContents {
+        start_line: usize,
+    },
     Draw,
 }
 
@@ -1,17 +1,33 @@
+use crate::editor::delta::*;
 use crate::editor::highlighting::*;
+use crate::editor::history::*;
+use crate::editor::selection::*;
 use crate::editor::text::*;
 use crate::editor::types::*;
 use crate::util::*;
+use core::ops::Range;
 use std::io::Write;
 
 pub struct View {
     start: usize,
     start_line: usize,
     dims: Rect,
+    document: Document,
+    history: History,
 
     cursor_blink_on: bool,
     cursor_pos: Point2<u32>,
 
+    // The anchor and head of each selection. Unlike `cursor_pos`, these are
+    // positions in the text, so they can't be past the end of a line.
+    selections: Vec<(Anchor, Anchor)>,
+
+    // How lines that don't fit on the screen get wrapped, and what goes in
+    // the gutter next to the rows that they wrap onto
+    wrap_mode: WrapMode,
+    indent_wraps: bool,
+    wrap_marker: Option<char>,
+
     visible_text: Pod<char>,
     highlighter: Highlighter,
 }
@@ -25,33 +41,46 @@ enum FlowResult {
     // Visual line, not textual
     FoundLine {
         end_pos: Point2<u32>,
-        begin: usize, // textual index
+        end: usize, // textual index
     },
 }
 
 impl View {
-    pub fn new(dims: Rect, s: &str) -> Self {
+    pub fn new(dims: Rect, mut document: Document) -> Self {
         let size = (dims.x * dims.y) as usize;
-        let mut visible_text = Pod::with_capacity(size);
-
-        let mut config = FlowConfig::new(s.chars(), Some(dims.x), Some(dims.y));
-        for (state, params) in &mut config {
-            visible_text.push(params.c);
-        }
-
+        let visible_text = Pod::with_capacity(size);
         let highlighter = Highlighter::from_gon(include_str!("../test_highlighter.gon"));
+        let selection = (
+            document.anchor(0, Gravity::Right),
+            document.anchor(0, Gravity::Right),
+        );
 
-        return Self {
+        let mut view = Self {
             start: 0,
             start_line: 0,
             dims,
+            document,
+            history: History::new(),
 
             cursor_blink_on: true,
             cursor_pos: Point2 { x: 0, y: 0 },
+            selections: vec![selection],
+
+            wrap_mode: WrapMode::Word,
+            indent_wraps: true,
+            wrap_marker: Some('\\'),
 
             visible_text,
             highlighter,
         };
+
+        view.load_window(0);
+
+        return view;
+    }
+
+    pub fn document(&self) -> &Document {
+        return &self.document;
     }
 
     pub fn run(&mut self, command: Command<ViewCommand>) {
@@ -62,8 +91,25 @@ impl View {
             ViewCommand::ToggleCursorBlink => self.toggle_cursor_blink(output),
             ViewCommand::Insert { text } => self.insert(text, output),
             ViewCommand::DeleteAfterCursor => self.delete(output),
-            ViewCommand::FlowCursor { index } => self.flow_cursor(index),
-            ViewCommand::SetContents(contents) => self.set_contents(contents, output),
+            ViewCommand::ExtendSelection(direction) => self.extend_selection(direction, output),
+            ViewCommand::AddCursorAbove => self.add_cursor(Direction::Up, output),
+            ViewCommand::AddCursorBelow => self.add_cursor(Direction::Down, output),
+            ViewCommand::AddNextOccurrence => self.add_next_occurrence(output),
+            ViewCommand::SplitSelectionIntoLines => self.split_selection_into_lines(output),
+            ViewCommand::SingleCursor => self.single_cursor(output),
+            ViewCommand::SetTabWidth { tab_width } => self.set_tab_width(tab_width, output),
+            ViewCommand::SetWrap {
+                mode,
+                indent,
+                marker,
+            } => self.set_wrap(mode, indent, marker, output),
+            ViewCommand::Undo => self.undo(output),
+            ViewCommand::Redo => self.redo(output),
+            ViewCommand::NextUndoBranch => self.next_undo_branch(),
+            ViewCommand::FlowCursor { index } => {
+                self.flow_cursor(index);
+            }
+            ViewCommand::SetContents { start_line } => self.set_contents(start_line, output),
             ViewCommand::Draw => self.draw(output),
         }
     }
@@ -77,20 +123,37 @@ impl View {
             text_bg_colors[range.range].fill(range.background);
         }
 
-        let mut config = FlowConfig::new(self.chars(), Some(self.dims.x), Some(self.dims.y));
+        let mut config = self.flow();
 
         let size = (self.dims.x * self.dims.y) as usize;
         let mut text = pod![' '; size];
         let mut fg_colors = pod![DEFAULT_FG; size];
         let mut bg_colors = pod![DEFAULT_BG; size];
         let mut line_numbers = pod![None; self.dims.y as usize];
+        let mut wrapped_rows = pod![false; self.dims.y as usize];
+        let mut row = None;
 
         let line = self.start_line + 1;
         let mut display_line = Some(line);
 
+        // The primary cursor is drawn from `cursor_pos` instead, since it can
+        // be off past the end of the text. Cursors on wide chars cover both
+        // of the char's cells.
+        let selections = self.selections();
+        let mut cursors = Vec::new();
+        let is_cursor = |index| selections[1..].iter().any(|s| s.head == index);
+        let cursor_idx = (self.cursor_pos.y * self.dims.x + self.cursor_pos.x) as usize;
+        let mut primary_cursor = cursor_idx..(cursor_idx + 1);
+
         for (state, params) in &mut config {
-            if state.pos.x == 0 {
-                line_numbers[state.pos.y as usize] = display_line.take();
+            if row != Some(state.pos.y) {
+                row = Some(state.pos.y);
+
+                let y = state.pos.y as usize;
+                match display_line.take() {
+                    Some(line) => line_numbers[y] = Some(line),
+                    None => wrapped_rows[y] = true,
+                }
             }
 
             let begin = (state.pos.y * self.dims.x + state.pos.x) as usize;
@@ -98,6 +161,20 @@ impl View {
             fg_colors[begin..end].fill(text_fg_colors[state.index]);
             bg_colors[begin..end].fill(text_bg_colors[state.index]);
 
+            let index = self.start + state.index;
+            if selections.iter().any(|s| s.range().contains(&index)) {
+                bg_colors[begin..end].fill(SELECTION_BG);
+            }
+
+            let cells = begin..end.max(begin + 1);
+            if is_cursor(index) {
+                cursors.push(cells.clone());
+            }
+
+            if state.pos == self.cursor_pos {
+                primary_cursor = cells;
+            }
+
             match params.c {
                 '\n' => {
                     display_line.replace(state.newline_count + line);
@@ -105,25 +182,37 @@ impl View {
 
                 c if c.is_whitespace() => {}
 
+                // Zero-width clusters don't get a cell of their own, and
+                // `begin` is the cell of whatever comes after them
+                _ if params.write_len == 0 => {}
+
                 c => {
-                    text[begin..end].fill(c);
+                    text[begin..end].fill(WIDE_CHAR_TAIL);
+                    text[begin] = c;
                 }
             }
         }
 
         // Fill remaining glyphs with the empty glyph
         let state = config.finalize();
-        if state.pos.y < self.dims.y && state.pos.x == 0 {
+        if state.pos.y < self.dims.y && row != Some(state.pos.y) {
             line_numbers[state.pos.y as usize] = display_line.take();
         }
 
+        if state.pos.y < self.dims.y && is_cursor(self.start + state.index) {
+            let idx = (state.pos.y * self.dims.x + state.pos.x) as usize;
+            cursors.push(idx..(idx + 1));
+        }
+
         debug_assert_eq!(line_numbers.len(), self.dims.y as usize);
 
         // clear block state
         if self.cursor_blink_on {
-            let idx = (self.cursor_pos.y * self.dims.x + self.cursor_pos.x) as usize;
-            fg_colors[idx] = DEFAULT_BG;
-            bg_colors[idx] = color(1.0, 1.0, 1.0);
+            cursors.push(primary_cursor);
+            for cells in cursors {
+                fg_colors[cells.clone()].fill(DEFAULT_BG);
+                bg_colors[cells].fill(color(1.0, 1.0, 1.0));
+            }
         }
 
         output.push(TedCommand::DrawView {
@@ -138,7 +227,7 @@ impl View {
         let line_size = LINES_WIDTH * self.dims.y as usize;
         let mut line_text = Pod::with_capacity(line_size);
 
-        for line in line_numbers {
+        for (line, is_wrapped) in line_numbers.into_iter().zip(wrapped_rows) {
             let mut write_to = [b' '; LINES_WIDTH];
             if let Some(line) = line {
                 let mut buf: &mut [u8] = &mut write_to;
@@ -149,6 +238,11 @@ impl View {
                 let c = unwrap(char::from_u32(b as u32));
                 line_text.push(c);
             }
+
+            if let (true, Some(marker)) = (is_wrapped, self.wrap_marker) {
+                line_text.pop();
+                line_text.push(marker);
+            }
         }
 
         let mut fg_colors = pod![LINES_FG; line_size];
@@ -163,18 +257,34 @@ impl View {
         });
     }
 
-    fn set_contents(&mut self, contents: SetContents, output: &mut Vec<TedCommand>) {
+    fn set_contents(&mut self, start_line: usize, output: &mut Vec<TedCommand>) {
+        self.load_window(start_line);
+        output.push(TedCommand::RequestRedraw);
+    }
+
+    // Re-reads the visible part of the document, e.g. after it's been edited
+    fn refresh(&mut self, output: &mut Vec<TedCommand>) {
+        self.set_contents(self.start_line, output);
+    }
+
+    // Fills the screen with the document, starting at `start_line`
+    fn load_window(&mut self, start_line: usize) {
+        let contents = self.document.contents(start_line, self.dims.y as usize);
         self.start = contents.start;
         self.start_line = contents.start_line;
         self.visible_text.clear();
 
-        let mut config =
-            FlowConfig::new(contents.text.chars(), Some(self.dims.x), Some(self.dims.y));
-        for (state, params) in &mut config {
-            self.visible_text.push(params.c);
-        }
+        let config = FlowConfig::new(contents.text.chars(), Some(self.dims.x), Some(self.dims.y));
+        let config = config
+            .with_tab_width(self.tab_width())
+            .with_wrap_mode(self.wrap_mode, self.indent_wraps);
 
-        output.push(TedCommand::RequestRedraw);
+        // Everything up to where the screen filled up, including whatever
+        // didn't get drawn, so that indices into this line up with the text
+        let flow = config.finalize();
+        for c in contents.text.chars().take(flow.index) {
+            self.visible_text.push(c);
+        }
     }
 
     fn insert(&mut self, s: String, output: &mut Vec<TedCommand>) {
@@ -186,88 +296,186 @@ impl View {
             }
         };
 
-        let (flow, result) = self.file_cursor();
+        let mut selections = self.selections();
+        let mut primary_text = s.clone();
+        if selections[0].is_empty() {
+            let (index, padding) = self.screen_cursor(first_char == '\n');
+            selections[0] = Selection::cursor(self.start + index);
+            primary_text = padding + &s;
+        }
 
-        let index = match result {
-            FlowResult::Found { index } => index,
+        self.set_selections(&selections);
+        let before = self.selections();
 
-            FlowResult::FoundLine { end_pos, begin } => {
-                let mut index = begin + end_pos.x as usize;
+        // Later edits go first, so that they don't move the earlier ones
+        let mut order: Vec<usize> = (0..before.len()).collect();
+        order.sort_by_key(|&i| core::cmp::Reverse(before[i].start()));
 
-                if first_char != '\n' {
-                    for x in end_pos.x..self.cursor_pos.x {
-                        self.visible_text.insert(index, '~');
-                        index += 1;
-                    }
-                }
+        let mut transaction = self.document.transaction();
+        for i in order {
+            let range = before[i].range();
+            let text = if i == 0 { &primary_text } else { &s };
+            transaction.delete(range.start, range.end);
+            transaction.insert(range.start, text);
+        }
 
-                index
-            }
+        // Selection anchors have right gravity, so every selection ends up as
+        // a cursor after the text that replaced it
+        let delta = transaction.commit();
+        self.set_selections(&self.selections());
 
-            FlowResult::NotFound => {
-                let mut index = flow.index;
-                for y in flow.pos.y..self.cursor_pos.y {
-                    self.visible_text.push('\n');
-                    index += 1;
+        self.history
+            .record(delta, EditKind::Insert, before, self.selections());
+        self.refresh(output);
+        self.sync_cursor();
+    }
+
+    fn delete(&mut self, output: &mut Vec<TedCommand>) {
+        let mut selections = self.selections();
+        if selections.len() == 1 && selections[0].is_empty() {
+            let (flow, result) = self.file_cursor();
+
+            match result {
+                FlowResult::Found { index } => {
+                    selections[0] = Selection::cursor(self.start + index)
+                }
+                _ => {
+                    self.cursor_move(Direction::Left, output);
+                    return;
                 }
+            }
+        }
 
-                if first_char != '\n' {
-                    for x in 0..self.cursor_pos.x {
-                        self.visible_text.push('~');
-                        index += 1;
-                    }
+        let mut ranges: Vec<Range<usize>> = selections
+            .iter()
+            .filter_map(|selection| match selection.is_empty() {
+                false => Some(selection.range()),
+                true if selection.head > 0 => {
+                    Some(self.document.prev_grapheme(selection.head)..selection.head)
                 }
+                true => None,
+            })
+            .collect();
 
-                index
-            }
-        };
+        if ranges.is_empty() {
+            output.push(TedCommand::RequestRedraw);
+            return;
+        }
 
-        let mut chars = Pod::with_capacity(s.len());
-        for c in s.chars() {
-            chars.push(c);
+        self.set_selections(&selections);
+        let before = self.selections();
+
+        ranges.sort_by_key(|range| core::cmp::Reverse(range.start));
+        let mut transaction = self.document.transaction();
+        for range in ranges {
+            transaction.delete(range.start, range.end);
         }
 
-        let count = chars.len();
+        let delta = transaction.commit();
+        self.set_selections(&self.selections());
 
-        self.visible_text.splice(index..index, &chars);
+        self.history
+            .record(delta, EditKind::Delete, before, self.selections());
+        self.refresh(output);
+        self.sync_cursor();
+    }
 
-        self.flow_cursor(index + count);
+    fn undo(&mut self, output: &mut Vec<TedCommand>) {
+        match self.history.undo() {
+            Some((delta, selections)) => self.restore(&delta, &selections, output),
+            None => output.push(TedCommand::RequestRedraw),
+        }
+    }
 
-        output.push(TedCommand::RequestRedraw);
+    fn redo(&mut self, output: &mut Vec<TedCommand>) {
+        match self.history.redo() {
+            Some((delta, selections)) => self.restore(&delta, &selections, output),
+            None => output.push(TedCommand::RequestRedraw),
+        }
     }
 
-    fn delete(&mut self, output: &mut Vec<TedCommand>) {
-        let (flow, result) = self.file_cursor();
+    // Nothing on screen changes until the next redo, which takes the branch
+    // that this picks
+    fn next_undo_branch(&mut self) {
+        self.history.next_branch();
+    }
 
-        let index = match result {
-            FlowResult::Found { index } => index,
-            _ => {
-                self.cursor_move(Direction::Left, output);
-                return;
-            }
-        };
+    fn restore(&mut self, delta: &Delta, selections: &[Selection], output: &mut Vec<TedCommand>) {
+        self.document.apply(delta);
+        self.set_selections(selections);
+        self.refresh(output);
+        self.sync_cursor();
+    }
 
-        let text_index = self.start + index;
-        if text_index == 0 {
-            output.push(TedCommand::RequestRedraw);
-            return;
+    // Where each selection is in the document. The first one is the primary
+    // selection, which is the one that the screen cursor belongs to.
+    fn selections(&self) -> Vec<Selection> {
+        let cursor = |anchor| unwrap(self.document.anchor_cursor(anchor));
+        let selections = self.selections.iter().map(|&(anchor, head)| Selection {
+            anchor: cursor(anchor),
+            head: cursor(head),
+        });
+
+        return selections.collect();
+    }
+
+    fn set_selections(&mut self, selections: &[Selection]) {
+        let selections = merge_selections(selections);
+        while self.selections.len() > selections.len() {
+            let (anchor, head) = unwrap(self.selections.pop());
+            self.document.remove_anchor(anchor);
+            self.document.remove_anchor(head);
+        }
+
+        for (i, selection) in selections.iter().enumerate() {
+            match self.selections.get(i) {
+                Some(&(anchor, head)) => {
+                    self.document.move_anchor(anchor, selection.anchor);
+                    self.document.move_anchor(head, selection.head);
+                }
+                None => {
+                    let anchor = self.document.anchor(selection.anchor, Gravity::Right);
+                    let head = self.document.anchor(selection.head, Gravity::Right);
+                    self.selections.push((anchor, head));
+                }
+            }
         }
+    }
 
-        self.visible_text.remove(index - 1);
-        self.flow_cursor(index - 1);
+    // The primary cursor's index into `visible_text`
+    fn cursor_index(&self) -> usize {
+        let (_, head) = self.selections[0];
+        let cursor = unwrap(self.document.anchor_cursor(head));
+        return cursor.saturating_sub(self.start);
+    }
 
-        output.push(TedCommand::RequestRedraw);
+    // Puts the screen cursor back on the primary cursor, scrolling to it first
+    // if it's not on the screen
+    fn sync_cursor(&mut self) {
+        let (_, head) = self.selections[0];
+        let cursor = unwrap(self.document.anchor_cursor(head));
+        let line = unwrap(self.document.line_for_cursor(cursor));
+        if line < self.start_line {
+            self.load_window(line);
+        }
+
+        // A line that's too long to fit on the screen can leave the cursor
+        // off the bottom even when the line starts at the top
+        while !self.flow_cursor(cursor - self.start) && self.start_line < line {
+            self.load_window(self.start_line + 1);
+        }
     }
 
-    fn flow_cursor(&mut self, index: usize) {
+    // Moves the screen cursor to `index` in the visible text, and returns
+    // false if that's not on the screen
+    fn flow_cursor(&mut self, index: usize) -> bool {
         self.cursor_blink_on = true;
         if self.visible_text.len() == 0 {
             self.cursor_pos = Point2 { x: 0, y: 0 };
-            return;
+            return index == 0;
         }
 
-        // TODO flowing past the end of the screen
-        let mut config = FlowConfig::new(self.chars(), Some(self.dims.x), Some(self.dims.y));
+        let mut config = self.flow();
 
         let mut next_pos = None;
         for (state, params) in &mut config {
@@ -284,6 +492,8 @@ impl View {
         if let Some(pos) = next_pos {
             self.cursor_pos = pos;
         }
+
+        return next_pos.is_some();
     }
 
     fn toggle_cursor_blink(&mut self, output: &mut Vec<TedCommand>) {
@@ -291,18 +501,27 @@ impl View {
         output.push(TedCommand::RequestRedraw);
     }
 
+    // The primary cursor moves around the screen freely, even where there's
+    // no text, while the rest of the cursors move through the text. Moving
+    // the primary cursor off the top or bottom of the screen scrolls by a
+    // line instead.
+    //
     // TODO This should maybe include settings and whatnot
     fn cursor_move(&mut self, direction: Direction, output: &mut Vec<TedCommand>) {
         match direction {
             Direction::Up => {
                 if self.cursor_pos.y > 0 {
                     self.cursor_pos.y -= 1;
+                } else if self.start_line > 0 {
+                    self.load_window(self.start_line - 1);
                 }
             }
 
             Direction::Down => {
                 if self.cursor_pos.y < self.dims.y - 1 {
                     self.cursor_pos.y += 1;
+                } else if self.start_line < self.document.newlines() {
+                    self.load_window(self.start_line + 1);
                 }
             }
 
@@ -319,42 +538,315 @@ impl View {
             }
         }
 
+        if let Some(x) = self.snapped_cursor_x(direction) {
+            self.cursor_pos.x = x;
+        }
+
+        let mut selections = self.selections();
+        let (index, _) = self.screen_cursor(false);
+        selections[0] = Selection::cursor(self.start + index);
+        for selection in &mut selections[1..] {
+            *selection = self.move_selection(*selection, direction, false);
+        }
+
+        self.set_selections(&selections);
+
         self.cursor_blink_on = true;
         output.push(TedCommand::RequestRedraw);
     }
 
+    // The screen cursor can't sit in the second cell of a wide char, so it
+    // goes to whichever side of the char it was headed towards
+    fn snapped_cursor_x(&self, direction: Direction) -> Option<u32> {
+        let mut config = self.flow();
+        for (state, params) in &mut config {
+            let (x, after) = (state.pos.x, state.pos.x + params.write_len);
+            let cursor_x = self.cursor_pos.x;
+            if state.pos.y != self.cursor_pos.y || cursor_x <= x || cursor_x >= after {
+                continue;
+            }
+
+            return match direction {
+                Direction::Right if after < self.dims.x => Some(after),
+                _ => Some(x),
+            };
+        }
+
+        return None;
+    }
+
+    fn extend_selection(&mut self, direction: Direction, output: &mut Vec<TedCommand>) {
+        let selections = self.selections();
+        let selections: Vec<Selection> = selections
+            .into_iter()
+            .map(|selection| self.move_selection(selection, direction, true))
+            .collect();
+
+        self.set_selections(&selections);
+        self.sync_cursor();
+        output.push(TedCommand::RequestRedraw);
+    }
+
+    // Moves the head of the selection through the text. Without `extend`,
+    // the selection turns into a cursor, and moving left or right out of a
+    // selection just goes to that end of it.
+    fn move_selection(
+        &self,
+        selection: Selection,
+        direction: Direction,
+        extend: bool,
+    ) -> Selection {
+        let head = selection.head;
+        let head = match direction {
+            Direction::Left if !extend && !selection.is_empty() => selection.start(),
+            Direction::Right if !extend && !selection.is_empty() => selection.end(),
+            Direction::Left => self.document.prev_grapheme(head),
+            Direction::Right => self.document.next_grapheme(head),
+
+            Direction::Up | Direction::Down => {
+                let (line, col) = unwrap(self.document.line_col::<Chars>(head));
+                let line = match direction {
+                    Direction::Up => line.checked_sub(1),
+                    _ => Some(line + 1),
+                };
+
+                match (
+                    line.and_then(|line| self.cursor_for_line_col(line, col)),
+                    direction,
+                ) {
+                    (Some(cursor), _) => cursor,
+                    (None, Direction::Up) => 0,
+                    (None, _) => self.document.len(),
+                }
+            }
+        };
+
+        return match extend {
+            true => Selection {
+                anchor: selection.anchor,
+                head,
+            },
+            false => Selection::cursor(head),
+        };
+    }
+
+    // Columns past the end of the line end up at the end of the line
+    fn cursor_for_line_col(&self, line: usize, col: usize) -> Option<usize> {
+        let begin = self.document.cursor_for_line(line)?;
+        let end = match self.document.cursor_for_line(line + 1) {
+            Some(next_line) => next_line - 1,
+            None => self.document.len(),
+        };
+
+        return Some(self.document.grapheme_floor((begin + col).min(end)));
+    }
+
+    fn add_cursor(&mut self, direction: Direction, output: &mut Vec<TedCommand>) {
+        let mut selections = self.selections();
+        let heads = selections.iter().map(|selection| selection.head);
+        let edge = match direction {
+            Direction::Up => unwrap(heads.min()),
+            _ => unwrap(heads.max()),
+        };
+
+        let cursor = self.move_selection(Selection::cursor(edge), direction, false);
+        let line = self.document.line_for_cursor(cursor.head);
+        if line != self.document.line_for_cursor(edge) {
+            selections.push(cursor);
+            self.set_selections(&selections);
+        }
+
+        output.push(TedCommand::RequestRedraw);
+    }
+
+    // With just a cursor, this selects the word that it's in; otherwise, it
+    // adds a selection at the next place that the primary selection's text
+    // shows up, wrapping around at the end of the document.
+    fn add_next_occurrence(&mut self, output: &mut Vec<TedCommand>) {
+        output.push(TedCommand::RequestRedraw);
+
+        let mut selections = self.selections();
+        let primary = selections[0];
+        if primary.is_empty() {
+            let word = self.word_at(primary.head);
+            if !word.is_empty() {
+                selections[0] = Selection {
+                    anchor: word.start,
+                    head: word.end,
+                };
+
+                self.set_selections(&selections);
+                self.sync_cursor();
+            }
+
+            return;
+        }
+
+        let needle = self.document.text_range(primary.range());
+        let after = unwrap(selections.iter().map(Selection::end).max());
+        let found = match self.document.find(&needle, after) {
+            Some(found) => Some(found),
+            None => self.document.find(&needle, 0),
+        };
+
+        let start = match found {
+            Some(found) => found,
+            None => return,
+        };
+
+        if selections
+            .iter()
+            .any(|selection| selection.start() == start)
+        {
+            return;
+        }
+
+        let end = start + primary.range().len();
+        selections.push(Selection {
+            anchor: start,
+            head: end,
+        });
+
+        self.set_selections(&selections);
+    }
+
+    fn word_at(&self, cursor: usize) -> Range<usize> {
+        let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+        let line = unwrap(self.document.line_for_cursor(cursor));
+        let begin = unwrap(self.document.cursor_for_line(line));
+        let text: Vec<char> = unwrap(self.document.text_for_line(line))
+            .flat_map(str::chars)
+            .collect();
+
+        let col = cursor - begin;
+        let before = text[..col].iter().rev().take_while(|c| is_word(c)).count();
+        let after = text[col..].iter().take_while(|c| is_word(c)).count();
+
+        return (cursor - before)..(cursor + after);
+    }
+
+    // Turns every selection into one selection per line that it covers
+    fn split_selection_into_lines(&mut self, output: &mut Vec<TedCommand>) {
+        let mut split = Vec::new();
+        for selection in self.selections() {
+            if selection.is_empty() {
+                split.push(selection);
+                continue;
+            }
+
+            let first = unwrap(self.document.line_for_cursor(selection.start()));
+            let last = unwrap(self.document.line_for_cursor(selection.end()));
+            for line in first..=last {
+                let begin = unwrap(self.cursor_for_line_col(line, 0));
+                let end = unwrap(self.cursor_for_line_col(line, usize::MAX - begin));
+                let (begin, end) = (begin.max(selection.start()), end.min(selection.end()));
+
+                // Selections that end at the beginning of a line don't really
+                // cover that line
+                if line != first && line == last && begin == end {
+                    continue;
+                }
+
+                split.push(Selection {
+                    anchor: begin,
+                    head: end,
+                });
+            }
+        }
+
+        self.set_selections(&split);
+        self.sync_cursor();
+        output.push(TedCommand::RequestRedraw);
+    }
+
+    fn single_cursor(&mut self, output: &mut Vec<TedCommand>) {
+        let primary = self.selections()[0];
+        self.set_selections(&[Selection::cursor(primary.head)]);
+        output.push(TedCommand::RequestRedraw);
+    }
+
+    // Where the screen cursor is in the visible text. The screen cursor can be
+    // past the end of its line, or below the end of the text, so this also
+    // returns the text that would pad things out to where the cursor is.
+    fn screen_cursor(&self, is_newline: bool) -> (usize, String) {
+        let mut padding = String::new();
+        let (flow, result) = self.file_cursor();
+
+        let index = match result {
+            FlowResult::Found { index } => index,
+
+            FlowResult::FoundLine { end_pos, end } => {
+                if !is_newline {
+                    for x in end_pos.x..self.cursor_pos.x {
+                        padding.push('~');
+                    }
+                }
+
+                end
+            }
+
+            FlowResult::NotFound => {
+                for y in flow.pos.y..self.cursor_pos.y {
+                    padding.push('\n');
+                }
+
+                if !is_newline {
+                    for x in 0..self.cursor_pos.x {
+                        padding.push('~');
+                    }
+                }
+
+                flow.index
+            }
+        };
+
+        return (index, padding);
+    }
+
     fn file_cursor(&self) -> (FlowState, FlowResult) {
-        let mut config = FlowConfig::new(self.chars(), Some(self.dims.x), Some(self.dims.y));
+        let mut config = self.flow();
 
         let mut found_line_end = false;
         let mut result = FlowResult::NotFound;
         for (state, params) in &mut config {
-            if state.pos == self.cursor_pos {
+            let (x, y) = (state.pos.x, state.pos.y);
+            // Cursors in the indentation of a wrapped row go to the start of
+            // the row
+            let width = params.write_len.max(1);
+            let is_indent = matches!(result, FlowResult::NotFound) && self.cursor_pos.x < x;
+            let is_on = (x..(x + width)).contains(&self.cursor_pos.x);
+            if y == self.cursor_pos.y && (is_on || is_indent) {
                 result = FlowResult::Found { index: state.index };
                 continue;
             }
 
+            // The line ends either at a newline, or where the text wraps
+            // around to the next line
+            let (end_pos, end) = match params.c {
+                '\n' => (state.pos, state.index),
+                _ => (Point2 { x: x + width, y }, state.index + params.len),
+            };
+
             match &mut result {
                 FlowResult::Found { .. } => continue,
-                FlowResult::FoundLine { end_pos, begin } => {
-                    if found_line_end {
-                        continue;
-                    }
-
-                    *end_pos = state.pos;
-
-                    if params.c == '\n' {
-                        found_line_end = true;
-                    }
+                FlowResult::FoundLine { .. } if found_line_end => continue,
+                // The line keeps going on the next row, so there's no need to
+                // pad it out to the cursor
+                FlowResult::FoundLine { end_pos, .. } if y != self.cursor_pos.y => {
+                    *end_pos = self.cursor_pos;
+                    found_line_end = true;
+                }
+                FlowResult::FoundLine {
+                    end_pos: line_end_pos,
+                    end: line_end,
+                } => {
+                    (*line_end_pos, *line_end) = (end_pos, end);
+                    found_line_end = params.c == '\n';
                 }
                 FlowResult::NotFound => {
-                    if state.pos.y == self.cursor_pos.y {
-                        let (end_pos, begin) = (state.pos, state.index);
-                        result = FlowResult::FoundLine { end_pos, begin };
-
-                        if params.c == '\n' {
-                            found_line_end = true;
-                        }
+                    if y == self.cursor_pos.y {
+                        result = FlowResult::FoundLine { end_pos, end };
+                        found_line_end = params.c == '\n';
                     }
                 }
             }
@@ -365,15 +857,41 @@ impl View {
             result = FlowResult::Found { index: flow.index };
         }
 
-        if let FlowResult::FoundLine { end_pos, begin } = &mut result {
-            if !found_line_end {
-                *end_pos = flow.pos;
-            }
-        }
-
         return (flow, result);
     }
 
+    fn flow<'a>(&'a self) -> FlowConfig<impl Iterator<Item = char> + 'a> {
+        let config = FlowConfig::new(self.chars(), Some(self.dims.x), Some(self.dims.y));
+        return config
+            .with_tab_width(self.tab_width())
+            .with_wrap_mode(self.wrap_mode, self.indent_wraps);
+    }
+
+    // The buffer's tab width if it has one, and otherwise the language's
+    fn tab_width(&self) -> u32 {
+        let tab_width = self.document.tab_width();
+        let tab_width = tab_width.or_else(|| self.highlighter.tab_width());
+        return tab_width.unwrap_or(DEFAULT_TAB_WIDTH);
+    }
+
+    fn set_wrap(
+        &mut self,
+        mode: WrapMode,
+        indent: bool,
+        marker: Option<char>,
+        output: &mut Vec<TedCommand>,
+    ) {
+        (self.wrap_mode, self.indent_wraps, self.wrap_marker) = (mode, indent, marker);
+        self.refresh(output);
+        self.sync_cursor();
+    }
+
+    fn set_tab_width(&mut self, tab_width: Option<u32>, output: &mut Vec<TedCommand>) {
+        self.document.set_tab_width(tab_width);
+        self.refresh(output);
+        self.sync_cursor();
+    }
+
     fn chars<'a>(&'a self) -> impl Iterator<Item = char> + 'a {
         // Previously attempted to replace this code with a handmade, non-generic
         // version. Ended up growing the binary from 237.69kb to 237.71kb.
@@ -381,3 +899,384 @@ impl View {
         return self.visible_text.iter().map(|c| *c);
     }
 }
+
+#[test]
+fn test_view_edits_document() {
+    let mut view = View::new(new_rect(10, 5), Document::from("ab\ncd"));
+    let buffer = &mut Vec::new();
+    let mut run = |view: &mut View, value| view.run(Command { buffer, value });
+    let text = |view: &View| view.document().chunks().collect::<String>();
+
+    run(
+        &mut view,
+        ViewCommand::Insert {
+            text: "x".to_string(),
+        },
+    );
+    assert_eq!(text(&view), "xab\ncd");
+    assert_eq!(view.chars().collect::<String>(), "xab\ncd");
+    assert!(view.cursor_pos == Point2 { x: 1, y: 0 });
+    assert_eq!(view.cursor_index(), 1);
+
+    run(&mut view, ViewCommand::DeleteAfterCursor);
+    assert_eq!(text(&view), "ab\ncd");
+    assert!(view.cursor_pos == Point2 { x: 0, y: 0 });
+
+    // Past the end of the line, so the line gets padded out to the cursor
+    run(&mut view, ViewCommand::CursorMove(Direction::Down));
+    for _ in 0..3 {
+        run(&mut view, ViewCommand::CursorMove(Direction::Right));
+    }
+
+    run(
+        &mut view,
+        ViewCommand::Insert {
+            text: "!".to_string(),
+        },
+    );
+    assert_eq!(text(&view), "ab\ncd~!");
+    assert_eq!(view.chars().collect::<String>(), "ab\ncd~!");
+
+    run(&mut view, ViewCommand::Undo);
+    assert_eq!(text(&view), "ab\ncd");
+    assert!(view.cursor_pos == Point2 { x: 2, y: 1 });
+
+    run(&mut view, ViewCommand::Undo);
+    assert_eq!(text(&view), "xab\ncd");
+    assert!(view.cursor_pos == Point2 { x: 1, y: 0 });
+
+    run(&mut view, ViewCommand::Redo);
+    assert_eq!(text(&view), "ab\ncd");
+    assert!(view.cursor_pos == Point2 { x: 0, y: 0 });
+
+    // Editing after an undo starts a new branch, and redo follows the newest
+    // one until it gets pointed somewhere else
+    run(
+        &mut view,
+        ViewCommand::Insert {
+            text: "?".to_string(),
+        },
+    );
+    run(&mut view, ViewCommand::Undo);
+    run(&mut view, ViewCommand::Redo);
+    assert_eq!(text(&view), "?ab\ncd");
+
+    run(&mut view, ViewCommand::Undo);
+    run(&mut view, ViewCommand::NextUndoBranch);
+    run(&mut view, ViewCommand::Redo);
+    assert_eq!(text(&view), "ab\ncd~!");
+}
+
+#[test]
+fn test_view_scrolls() {
+    let mut view = View::new(new_rect(10, 3), Document::from("a\nb\nc\nd\ne"));
+    let buffer = &mut Vec::new();
+    let mut run = |view: &mut View, value| view.run(Command { buffer, value });
+    let text = |view: &View| view.document().chunks().collect::<String>();
+    let insert = |text: &str| ViewCommand::Insert {
+        text: text.to_string(),
+    };
+
+    // Moving down off the bottom of the screen scrolls down a line, so lines
+    // that weren't on the screen can be edited
+    for _ in 0..3 {
+        run(&mut view, ViewCommand::CursorMove(Direction::Down));
+    }
+    assert_eq!(view.start_line, 1);
+    assert!(view.cursor_pos == Point2 { x: 0, y: 2 });
+    run(&mut view, ViewCommand::CursorMove(Direction::Right));
+    run(&mut view, insert("!"));
+    assert_eq!(text(&view), "a\nb\nc\nd!\ne");
+    assert!(view.chars().collect::<String>().starts_with("b\nc\nd!"));
+
+    // A new line at the bottom of the screen scrolls to keep the cursor on
+    // the screen
+    run(&mut view, insert("\n"));
+    assert_eq!(text(&view), "a\nb\nc\nd!\n\ne");
+    assert_eq!(view.start_line, 2);
+    assert!(view.cursor_pos == Point2 { x: 0, y: 2 });
+
+    for _ in 0..4 {
+        run(&mut view, ViewCommand::CursorMove(Direction::Up));
+    }
+    assert_eq!(view.start_line, 0);
+    assert!(view.cursor_pos == Point2 { x: 0, y: 0 });
+
+    // Undo goes back to wherever the edit was
+    run(&mut view, ViewCommand::Undo);
+    assert_eq!(text(&view), "a\nb\nc\nd!\ne");
+    assert_eq!(view.start_line, 1);
+    assert!(view.cursor_pos == Point2 { x: 2, y: 2 });
+
+    // Deleting at the top of the screen joins the line to the one above it,
+    // which is off the screen
+    run(&mut view, ViewCommand::SetContents { start_line: 3 });
+    assert_eq!(view.chars().collect::<String>(), "d!\ne");
+    for _ in 0..2 {
+        run(&mut view, ViewCommand::CursorMove(Direction::Up));
+        run(&mut view, ViewCommand::CursorMove(Direction::Left));
+    }
+    run(&mut view, ViewCommand::DeleteAfterCursor);
+    assert_eq!(text(&view), "a\nb\ncd!\ne");
+    assert_eq!(view.start_line, 2);
+    assert!(view.cursor_pos == Point2 { x: 1, y: 0 });
+}
+
+#[test]
+fn test_view_multiple_cursors() {
+    let mut view = View::new(new_rect(20, 5), Document::from("foo bar\nfoo baz\nfoo"));
+    let buffer = &mut Vec::new();
+    let mut run = |view: &mut View, value| view.run(Command { buffer, value });
+    let text = |view: &View| view.document().chunks().collect::<String>();
+    let insert = |text: &str| ViewCommand::Insert {
+        text: text.to_string(),
+    };
+
+    // Selects the word, then each place that it shows up
+    run(&mut view, ViewCommand::AddNextOccurrence);
+    assert_eq!(view.selections(), [Selection { anchor: 0, head: 3 }]);
+    run(&mut view, ViewCommand::AddNextOccurrence);
+    run(&mut view, ViewCommand::AddNextOccurrence);
+    run(&mut view, ViewCommand::AddNextOccurrence);
+    assert_eq!(view.selections().len(), 3);
+
+    run(&mut view, insert("x"));
+    assert_eq!(text(&view), "x bar\nx baz\nx");
+    run(&mut view, insert("y"));
+    assert_eq!(text(&view), "xy bar\nxy baz\nxy");
+
+    for _ in 0..2 {
+        run(&mut view, ViewCommand::DeleteAfterCursor);
+    }
+    assert_eq!(text(&view), " bar\n baz\n");
+
+    run(&mut view, ViewCommand::Undo);
+    assert_eq!(text(&view), "xy bar\nxy baz\nxy");
+    assert_eq!(view.selections().len(), 3);
+    run(&mut view, ViewCommand::Undo);
+    assert_eq!(text(&view), "foo bar\nfoo baz\nfoo");
+    assert_eq!(
+        view.selections()[2],
+        Selection {
+            anchor: 16,
+            head: 19
+        }
+    );
+
+    // Cursors that run into each other turn into one
+    run(&mut view, ViewCommand::SingleCursor);
+    run(&mut view, ViewCommand::AddCursorBelow);
+    run(&mut view, ViewCommand::AddCursorBelow);
+    run(&mut view, ViewCommand::AddCursorBelow);
+    assert_eq!(
+        view.selections(),
+        [
+            Selection::cursor(3),
+            Selection::cursor(11),
+            Selection::cursor(19)
+        ]
+    );
+    run(&mut view, ViewCommand::CursorMove(Direction::Down));
+    run(&mut view, ViewCommand::CursorMove(Direction::Down));
+    assert_eq!(view.selections(), [Selection::cursor(19)]);
+
+    run(&mut view, ViewCommand::AddCursorAbove);
+    run(&mut view, insert("!"));
+    assert_eq!(text(&view), "foo bar\nfoo! baz\nfoo!");
+
+    // One selection per line, and the last line isn't covered
+    run(&mut view, ViewCommand::SingleCursor);
+    let mut view = View::new(new_rect(20, 5), Document::from("ab\ncd\nef"));
+    for _ in 0..6 {
+        run(&mut view, ViewCommand::ExtendSelection(Direction::Right));
+    }
+    assert_eq!(view.selections(), [Selection { anchor: 0, head: 6 }]);
+    run(&mut view, ViewCommand::SplitSelectionIntoLines);
+    assert_eq!(
+        view.selections(),
+        [
+            Selection { anchor: 0, head: 2 },
+            Selection { anchor: 3, head: 5 }
+        ]
+    );
+    run(&mut view, insert("-"));
+    assert_eq!(text(&view), "-\n-\nef");
+}
+
+#[test]
+fn test_view_graphemes() {
+    let mut view = View::new(new_rect(10, 5), Document::from("ae\u{301}b\nxyz"));
+    let buffer = &mut Vec::new();
+    let mut run = |view: &mut View, value| view.run(Command { buffer, value });
+    let text = |view: &View| view.document().chunks().collect::<String>();
+
+    // Each cluster is one cell, so the cursor goes over the accent with it
+    run(&mut view, ViewCommand::CursorMove(Direction::Right));
+    run(&mut view, ViewCommand::CursorMove(Direction::Right));
+    assert_eq!(view.cursor_index(), 3);
+    assert_eq!(view.selections(), [Selection::cursor(3)]);
+
+    run(&mut view, ViewCommand::DeleteAfterCursor);
+    assert_eq!(text(&view), "ab\nxyz");
+    assert_eq!(view.cursor_index(), 1);
+
+    run(&mut view, ViewCommand::Undo);
+    assert_eq!(text(&view), "ae\u{301}b\nxyz");
+
+    // Cursors that move through the text skip over the whole cluster too
+    run(&mut view, ViewCommand::SingleCursor);
+    run(&mut view, ViewCommand::AddCursorBelow);
+    run(&mut view, ViewCommand::ExtendSelection(Direction::Left));
+    assert_eq!(
+        view.selections(),
+        [
+            Selection { anchor: 3, head: 1 },
+            Selection { anchor: 8, head: 7 }
+        ]
+    );
+
+    // The column on the next line would be in the middle of the cluster
+    run(&mut view, ViewCommand::SingleCursor);
+    let mut view = View::new(new_rect(10, 5), Document::from("abc\nae\u{301}"));
+    run(&mut view, ViewCommand::CursorMove(Direction::Right));
+    run(&mut view, ViewCommand::CursorMove(Direction::Right));
+    run(&mut view, ViewCommand::AddCursorBelow);
+    assert_eq!(view.selections()[1], Selection::cursor(5));
+}
+
+#[test]
+fn test_view_wide_chars() {
+    let mut view = View::new(new_rect(5, 5), Document::from("日本語x"));
+    let buffer = &mut Vec::new();
+    let mut run = |view: &mut View, value| view.run(Command { buffer, value });
+    let text = |view: &View| view.document().chunks().collect::<String>();
+
+    // The cursor goes over a wide char in one step
+    run(&mut view, ViewCommand::CursorMove(Direction::Right));
+    assert!(view.cursor_pos == Point2 { x: 2, y: 0 });
+    assert_eq!(view.cursor_index(), 1);
+
+    // The last cell of the line is empty, since "語" didn't fit there
+    run(&mut view, ViewCommand::CursorMove(Direction::Right));
+    assert!(view.cursor_pos == Point2 { x: 4, y: 0 });
+    assert_eq!(view.cursor_index(), 2);
+
+    run(&mut view, ViewCommand::CursorMove(Direction::Down));
+    run(&mut view, ViewCommand::CursorMove(Direction::Left));
+    run(&mut view, ViewCommand::CursorMove(Direction::Left));
+    assert!(view.cursor_pos == Point2 { x: 2, y: 1 });
+    run(&mut view, ViewCommand::CursorMove(Direction::Left));
+    assert!(view.cursor_pos == Point2 { x: 0, y: 1 });
+    assert_eq!(view.cursor_index(), 2);
+
+    run(&mut view, ViewCommand::CursorMove(Direction::Up));
+    run(&mut view, ViewCommand::CursorMove(Direction::Right));
+    run(&mut view, ViewCommand::CursorMove(Direction::Right));
+    run(
+        &mut view,
+        ViewCommand::Insert {
+            text: "!".to_string(),
+        },
+    );
+    assert_eq!(text(&view), "日本!語x");
+
+    let mut out = Vec::new();
+    view.draw(&mut out);
+    let cells = match &out[0] {
+        TedCommand::DrawView { text, .. } => text.iter().take(10).copied().collect::<String>(),
+        _ => panic!("expected the text to get drawn first"),
+    };
+    let tail = WIDE_CHAR_TAIL;
+    assert_eq!(cells, format!("日{tail}本{tail}!語{tail}x  "));
+
+    // A zero-width char doesn't draw over the char after it
+    let mut view = View::new(new_rect(6, 2), Document::from("ab\u{200B} c"));
+    let mut out = Vec::new();
+    view.draw(&mut out);
+    let cells = match &out[0] {
+        TedCommand::DrawView { text, .. } => text.iter().take(6).copied().collect::<String>(),
+        _ => panic!("expected the text to get drawn first"),
+    };
+    assert_eq!(cells, "ab c  ");
+}
+
+#[test]
+fn test_view_tabs() {
+    let mut view = View::new(new_rect(20, 5), Document::from("\tab\n  \tc"));
+    let buffer = &mut Vec::new();
+    let mut run = |view: &mut View, value| view.run(Command { buffer, value });
+
+    // The tab is one char that's a few cells wide
+    run(&mut view, ViewCommand::CursorMove(Direction::Right));
+    assert!(view.cursor_pos == Point2 { x: 4, y: 0 });
+    assert_eq!(view.cursor_index(), 1);
+
+    run(&mut view, ViewCommand::CursorMove(Direction::Down));
+    assert!(view.cursor_pos == Point2 { x: 4, y: 1 });
+    assert_eq!(view.cursor_index(), 7);
+
+    run(&mut view, ViewCommand::CursorMove(Direction::Left));
+    assert!(view.cursor_pos == Point2 { x: 2, y: 1 });
+    assert_eq!(view.cursor_index(), 6);
+
+    // The buffer's own setting wins over the language's
+    run(&mut view, ViewCommand::SetTabWidth { tab_width: Some(8) });
+    assert!(view.cursor_pos == Point2 { x: 2, y: 1 });
+    run(&mut view, ViewCommand::CursorMove(Direction::Right));
+    assert!(view.cursor_pos == Point2 { x: 8, y: 1 });
+    assert_eq!(view.cursor_index(), 7);
+
+    run(&mut view, ViewCommand::SetTabWidth { tab_width: None });
+    assert!(view.cursor_pos == Point2 { x: 4, y: 1 });
+}
+
+#[test]
+fn test_view_word_wrap() {
+    let mut view = View::new(new_rect(8, 5), Document::from("hello world"));
+    let buffer = &mut Vec::new();
+    let mut run = |view: &mut View, value| view.run(Command { buffer, value });
+    let text = |view: &View| view.document().chunks().collect::<String>();
+
+    let mut out = Vec::new();
+    view.draw(&mut out);
+    let gutter = match &out[1] {
+        TedCommand::DrawView { text, .. } => text.iter().take(9).copied().collect::<String>(),
+        _ => panic!("expected the line numbers to get drawn second"),
+    };
+    assert_eq!(gutter, "  1  \\   ");
+
+    // Past the end of a wrapped row, text goes at the end of the row, without
+    // any padding
+    for _ in 0..7 {
+        run(&mut view, ViewCommand::CursorMove(Direction::Right));
+    }
+    run(
+        &mut view,
+        ViewCommand::Insert {
+            text: "X".to_string(),
+        },
+    );
+    assert_eq!(text(&view), "hello Xworld");
+
+    // In the indentation of a wrapped row, text goes at the start of the row
+    let mut view = View::new(new_rect(6, 5), Document::from("  ab cd"));
+    run(&mut view, ViewCommand::CursorMove(Direction::Down));
+    run(
+        &mut view,
+        ViewCommand::Insert {
+            text: "X".to_string(),
+        },
+    );
+    assert_eq!(text(&view), "  ab Xcd");
+
+    run(
+        &mut view,
+        ViewCommand::SetWrap {
+            mode: WrapMode::Char,
+            indent: false,
+            marker: None,
+        },
+    );
+    assert_eq!(view.cursor_index(), 6);
+    assert!(view.cursor_pos == Point2 { x: 0, y: 1 });
+}
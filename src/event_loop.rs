@@ -110,12 +110,50 @@ impl Handler {
                 #[allow(deprecated)]
                 let modifiers = input.modifiers;
 
-                if modifiers.ctrl() || modifiers.logo() || modifiers.alt() {
-                    return None;
+                let direction = Direction::from_arrow_key(key);
+                if modifiers.ctrl() || modifiers.logo() {
+                    if modifiers.alt() {
+                        return match (direction, key) {
+                            (Some(Direction::Up), _) => Some(for_view(ViewCommand::AddCursorAbove)),
+                            (Some(Direction::Down), _) => {
+                                Some(for_view(ViewCommand::AddCursorBelow))
+                            }
+                            (_, event::VirtualKeyCode::Z) => {
+                                Some(for_view(ViewCommand::NextUndoBranch))
+                            }
+                            _ => None,
+                        };
+                    }
+
+                    return match (key, modifiers.shift()) {
+                        (event::VirtualKeyCode::Z, false) => Some(for_view(ViewCommand::Undo)),
+                        (event::VirtualKeyCode::Z, true) => Some(for_view(ViewCommand::Redo)),
+                        (event::VirtualKeyCode::Y, _) => Some(for_view(ViewCommand::Redo)),
+                        (event::VirtualKeyCode::D, _) => {
+                            Some(for_view(ViewCommand::AddNextOccurrence))
+                        }
+                        _ => None,
+                    };
+                }
+
+                if modifiers.alt() {
+                    return match (key, modifiers.shift()) {
+                        (event::VirtualKeyCode::I, true) => {
+                            Some(for_view(ViewCommand::SplitSelectionIntoLines))
+                        }
+                        _ => None,
+                    };
+                }
+
+                if let Some(direction) = direction {
+                    return match modifiers.shift() {
+                        true => Some(for_view(ViewCommand::ExtendSelection(direction))),
+                        false => Some(for_view(ViewCommand::CursorMove(direction))),
+                    };
                 }
 
-                if let Some(direction) = Direction::from_arrow_key(key) {
-                    return Some(for_view(ViewCommand::CursorMove(direction)));
+                if key == event::VirtualKeyCode::Escape {
+                    return Some(for_view(ViewCommand::SingleCursor));
                 }
 
                 if key == event::VirtualKeyCode::Back {